@@ -0,0 +1,150 @@
+use crate::Error;
+use async_session::async_trait;
+use sqlx::mysql::MySqlConnection;
+use std::sync::Arc;
+
+pub mod server;
+
+const DEFAULT_ZONE: &str = "u.isucon.dev";
+const DEFAULT_PDNS_DATABASE: &str = "isudns";
+
+/// 登録されたユーザ名から `<name>.u.isucon.dev` の A レコードを作成する。
+///
+/// `add_record` はユーザ登録のトランザクション内で呼ばれ、失敗した場合はユーザ登録ごとロールバックされる。
+/// トランザクションの外部に副作用を持つ実装は、`add_record` 以降のユーザ登録の失敗時に
+/// `remove_record` で取り消せるようにしておく。
+#[async_trait]
+pub trait DnsRegistrar: Send + Sync {
+    async fn add_record(&self, tx: &mut MySqlConnection, name: &str) -> Result<(), Error>;
+    async fn remove_record(&self, tx: &mut MySqlConnection, name: &str) -> Result<(), Error>;
+}
+
+/// `ISUCON13_DNS_REGISTRAR` の値からバックエンドを選択する。
+///
+/// - `pdnsutil`: Go 実装と同様に `pdnsutil add-record` を実行する
+/// - `mysql`: PowerDNS の MySQL バックエンドの `records` テーブルへ直接 INSERT する
+/// - `none` (デフォルト): 何もしない (ワイルドカードレコードで解決する場合)
+pub fn build_dns_registrar() -> Arc<dyn DnsRegistrar> {
+    let backend = std::env::var("ISUCON13_DNS_REGISTRAR").unwrap_or_default();
+    let zone = zone();
+    match backend.as_str() {
+        "pdnsutil" => Arc::new(PdnsutilRegistrar {
+            zone,
+            address: subdomain_address(),
+        }),
+        "mysql" => Arc::new(PdnsMySqlRegistrar {
+            zone,
+            address: subdomain_address(),
+            database: std::env::var("ISUCON13_POWERDNS_DATABASE")
+                .unwrap_or_else(|_| DEFAULT_PDNS_DATABASE.to_owned()),
+        }),
+        "" | "none" => Arc::new(NoopRegistrar),
+        other => panic!("unknown ISUCON13_DNS_REGISTRAR: {other}"),
+    }
+}
+
+fn zone() -> String {
+    std::env::var("ISUCON13_DNS_ZONE").unwrap_or_else(|_| DEFAULT_ZONE.to_owned())
+}
+
+fn subdomain_address() -> String {
+    std::env::var("ISUCON13_POWERDNS_SUBDOMAIN_ADDRESS")
+        .expect("environ ISUCON13_POWERDNS_SUBDOMAIN_ADDRESS must be provided")
+}
+
+/// `pdnsutil` コマンドを実行してレコードを登録する。
+pub struct PdnsutilRegistrar {
+    zone: String,
+    address: String,
+}
+
+impl PdnsutilRegistrar {
+    async fn run(&self, args: &[&str]) -> Result<(), Error> {
+        let output = tokio::process::Command::new("pdnsutil")
+            .args(args)
+            .output()
+            .await?;
+        if !output.status.success() {
+            return Err(Error::Dns(format!(
+                "pdnsutil {} failed with stdout={} stderr={}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr),
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DnsRegistrar for PdnsutilRegistrar {
+    async fn add_record(&self, _tx: &mut MySqlConnection, name: &str) -> Result<(), Error> {
+        self.run(&["add-record", &self.zone, name, "A", "0", &self.address])
+            .await
+    }
+
+    async fn remove_record(&self, _tx: &mut MySqlConnection, name: &str) -> Result<(), Error> {
+        self.run(&["delete-rrset", &self.zone, name, "A"]).await
+    }
+}
+
+/// PowerDNS の gmysql バックエンドと同じ MySQL サーバを使っている前提で、
+/// ユーザ登録と同じトランザクションから `records` テーブルへ書き込む。
+pub struct PdnsMySqlRegistrar {
+    zone: String,
+    address: String,
+    database: String,
+}
+
+impl PdnsMySqlRegistrar {
+    fn fqdn(&self, name: &str) -> String {
+        format!("{}.{}", name, self.zone)
+    }
+}
+
+#[async_trait]
+impl DnsRegistrar for PdnsMySqlRegistrar {
+    async fn add_record(&self, tx: &mut MySqlConnection, name: &str) -> Result<(), Error> {
+        let query = format!(
+            "INSERT INTO `{0}`.records (domain_id, name, type, content, ttl, prio, disabled, auth) SELECT id, ?, 'A', ?, 0, 0, 0, 1 FROM `{0}`.domains WHERE name = ?",
+            self.database
+        );
+        let rs = sqlx::query(&query)
+            .bind(self.fqdn(name))
+            .bind(&self.address)
+            .bind(&self.zone)
+            .execute(&mut *tx)
+            .await?;
+        if rs.rows_affected() == 0 {
+            return Err(Error::Dns(format!("zone {} is not found", self.zone)));
+        }
+        Ok(())
+    }
+
+    async fn remove_record(&self, tx: &mut MySqlConnection, name: &str) -> Result<(), Error> {
+        let query = format!(
+            "DELETE FROM `{}`.records WHERE name = ? AND type = 'A'",
+            self.database
+        );
+        sqlx::query(&query)
+            .bind(self.fqdn(name))
+            .execute(&mut *tx)
+            .await?;
+        Ok(())
+    }
+}
+
+/// レコードを登録しない。ゾーンのワイルドカードで解決する環境やテスト用。
+pub struct NoopRegistrar;
+
+#[async_trait]
+impl DnsRegistrar for NoopRegistrar {
+    async fn add_record(&self, _tx: &mut MySqlConnection, name: &str) -> Result<(), Error> {
+        tracing::debug!("skip registering DNS record for {}", name);
+        Ok(())
+    }
+
+    async fn remove_record(&self, _tx: &mut MySqlConnection, _name: &str) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+//! `*.u.isucon.dev` の権威 DNS サーバ。
+//!
+//! 存在するサブドメインはメモリ上の `NameSet` だけで判定するので、
+//! ランダムなサブドメインへの問い合わせが大量に来ても MySQL には到達しない。
+
+use sqlx::mysql::MySqlConnection;
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+const TTL: u32 = 60;
+const TYPE_A: u16 = 1;
+const TYPE_NS: u16 = 2;
+const TYPE_SOA: u16 = 6;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+const RCODE_NOERROR: u8 = 0;
+const RCODE_FORMERR: u8 = 1;
+const RCODE_NXDOMAIN: u8 = 3;
+const RCODE_NOTIMP: u8 = 4;
+const RCODE_REFUSED: u8 = 5;
+
+/// ゾーン内で常に存在する名前
+const STATIC_NAMES: &[&str] = &["ns1", "pipe"];
+
+/// 登録済みユーザ名 (小文字) の集合
+#[derive(Clone, Default)]
+pub struct NameSet(Arc<RwLock<HashSet<String>>>);
+
+impl NameSet {
+    pub fn insert(&self, name: &str) {
+        self.0.write().unwrap().insert(name.to_ascii_lowercase());
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        STATIC_NAMES.contains(&name) || self.0.read().unwrap().contains(name)
+    }
+
+    /// users テーブルから読み直す
+    pub async fn reload(&self, conn: &mut MySqlConnection) -> sqlx::Result<()> {
+        let names: Vec<String> = sqlx::query_scalar("SELECT name FROM users")
+            .fetch_all(conn)
+            .await?;
+        let names = names.into_iter().map(|n| n.to_ascii_lowercase()).collect();
+        *self.0.write().unwrap() = names;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub listen: SocketAddr,
+    /// ドット区切り、小文字、末尾ドットなし
+    pub zone: String,
+    pub address: Ipv4Addr,
+}
+
+impl Config {
+    /// `ISUCON13_DNS_SERVER_ADDRESS` が設定されている場合のみサーバを起動する
+    pub fn from_env() -> Option<Self> {
+        let listen = std::env::var("ISUCON13_DNS_SERVER_ADDRESS").ok()?;
+        Some(Self {
+            listen: listen
+                .parse()
+                .expect("ISUCON13_DNS_SERVER_ADDRESS must be a socket address"),
+            zone: super::zone().to_ascii_lowercase(),
+            address: super::subdomain_address()
+                .parse()
+                .expect("ISUCON13_POWERDNS_SUBDOMAIN_ADDRESS must be an IPv4 address"),
+        })
+    }
+}
+
+/// UDP と TCP の両方で待ち受けるタスクを起動する
+pub async fn spawn(config: Config, names: NameSet) -> std::io::Result<()> {
+    let udp = UdpSocket::bind(config.listen).await?;
+    let tcp = TcpListener::bind(config.listen).await?;
+    tracing::info!("DNS server listening on {}", config.listen);
+
+    let zone = Arc::new(Zone { config, names });
+    tokio::spawn(serve_udp(udp, zone.clone()));
+    tokio::spawn(serve_tcp(tcp, zone));
+    Ok(())
+}
+
+async fn serve_udp(socket: UdpSocket, zone: Arc<Zone>) {
+    let mut buf = [0u8; 512];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(r) => r,
+            Err(e) => {
+                tracing::warn!("DNS udp recv error: {}", e);
+                continue;
+            }
+        };
+        if let Some(res) = zone.handle(&buf[..len]) {
+            if let Err(e) = socket.send_to(&res, peer).await {
+                tracing::warn!("DNS udp send error: {}", e);
+            }
+        }
+    }
+}
+
+async fn serve_tcp(listener: TcpListener, zone: Arc<Zone>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(serve_tcp_conn(stream, zone.clone()));
+            }
+            Err(e) => tracing::warn!("DNS tcp accept error: {}", e),
+        }
+    }
+}
+
+async fn serve_tcp_conn(mut stream: TcpStream, zone: Arc<Zone>) -> std::io::Result<()> {
+    loop {
+        let len = match stream.read_u16().await {
+            Ok(len) => len as usize,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let mut buf = vec![0u8; len];
+        stream.read_exact(&mut buf).await?;
+        let Some(res) = zone.handle(&buf) else {
+            return Ok(());
+        };
+        stream.write_u16(res.len() as u16).await?;
+        stream.write_all(&res).await?;
+    }
+}
+
+struct Zone {
+    config: Config,
+    names: NameSet,
+}
+
+struct Question<'a> {
+    /// 小文字化したラベル
+    labels: Vec<String>,
+    qtype: u16,
+    qclass: u16,
+    /// ヘッダ直後から question セクション末尾まで
+    raw: &'a [u8],
+}
+
+impl Zone {
+    /// 問い合わせを処理して応答を返す。応答すべきでないパケットなら None
+    fn handle(&self, req: &[u8]) -> Option<Vec<u8>> {
+        if req.len() < 12 {
+            return None;
+        }
+        // QR ビットが立っているものは応答なので無視する
+        if req[2] & 0x80 != 0 {
+            return None;
+        }
+        let opcode = (req[2] >> 3) & 0x0f;
+        if opcode != 0 {
+            return Some(self.reply(req, None, RCODE_NOTIMP));
+        }
+        let qdcount = u16::from_be_bytes([req[4], req[5]]);
+        if qdcount != 1 {
+            return Some(self.reply(req, None, RCODE_FORMERR));
+        }
+        let Some(question) = parse_question(req) else {
+            return Some(self.reply(req, None, RCODE_FORMERR));
+        };
+        if question.qclass != CLASS_IN {
+            return Some(self.reply(req, Some(&question), RCODE_REFUSED));
+        }
+
+        let zone_labels: Vec<&str> = self.config.zone.split('.').collect();
+        let Some(sub) = question
+            .labels
+            .len()
+            .checked_sub(zone_labels.len())
+            .filter(|&n| question.labels[n..].iter().eq(zone_labels.iter()))
+        else {
+            return Some(self.reply(req, Some(&question), RCODE_REFUSED));
+        };
+
+        let mut answers = Vec::new();
+        let exists = match sub {
+            0 => {
+                if matches!(question.qtype, TYPE_SOA | TYPE_ANY) {
+                    answers.push(self.soa_record());
+                }
+                if matches!(question.qtype, TYPE_NS | TYPE_ANY) {
+                    answers.push(self.ns_record());
+                }
+                true
+            }
+            1 => self.names.contains(&question.labels[0]),
+            _ => false,
+        };
+        if !exists {
+            return Some(self.answer(req, &question, RCODE_NXDOMAIN, &[]));
+        }
+        if matches!(question.qtype, TYPE_A | TYPE_ANY) {
+            answers.push(self.a_record());
+        }
+        Some(self.answer(req, &question, RCODE_NOERROR, &answers))
+    }
+
+    fn reply(&self, req: &[u8], question: Option<&Question>, rcode: u8) -> Vec<u8> {
+        let mut res = header(req, rcode, question.is_some() as u16, 0, 0);
+        if let Some(q) = question {
+            res.extend_from_slice(q.raw);
+        }
+        res
+    }
+
+    /// 応答を組み立てる。回答がなければ負のキャッシュ用に SOA を authority に入れる
+    fn answer(&self, req: &[u8], question: &Question, rcode: u8, answers: &[Vec<u8>]) -> Vec<u8> {
+        let nscount = answers.is_empty() as u16;
+        let mut res = header(req, rcode, 1, answers.len() as u16, nscount);
+        res.extend_from_slice(question.raw);
+        for record in answers {
+            res.extend_from_slice(record);
+        }
+        if answers.is_empty() {
+            res.extend_from_slice(&self.soa_record());
+        }
+        res
+    }
+
+    fn a_record(&self) -> Vec<u8> {
+        // 0xc00c は question セクションの名前へのポインタ
+        record(&[0xc0, 0x0c], TYPE_A, &self.config.address.octets())
+    }
+
+    fn ns_record(&self) -> Vec<u8> {
+        let rdata = encode_name(&format!("ns1.{}", self.config.zone));
+        record(&encode_name(&self.config.zone), TYPE_NS, &rdata)
+    }
+
+    fn soa_record(&self) -> Vec<u8> {
+        let mut rdata = encode_name(&format!("ns1.{}", self.config.zone));
+        rdata.extend(encode_name(&format!("hostmaster.{}", self.config.zone)));
+        // serial, refresh, retry, expire, minimum (u.isucon.dev.zone と同じ値)
+        for v in [0u32, 10800, 3600, 604800, 3600] {
+            rdata.extend_from_slice(&v.to_be_bytes());
+        }
+        record(&encode_name(&self.config.zone), TYPE_SOA, &rdata)
+    }
+}
+
+fn header(req: &[u8], rcode: u8, qdcount: u16, ancount: u16, nscount: u16) -> Vec<u8> {
+    let mut res = Vec::with_capacity(512);
+    res.extend_from_slice(&req[0..2]);
+    // QR=1, opcode はそのまま, AA=1, RD はそのまま
+    res.push(0x80 | (req[2] & 0x78) | 0x04 | (req[2] & 0x01));
+    res.push(rcode & 0x0f);
+    for n in [qdcount, ancount, nscount, 0] {
+        res.extend_from_slice(&n.to_be_bytes());
+    }
+    res
+}
+
+fn parse_question(req: &[u8]) -> Option<Question<'_>> {
+    let mut pos = 12;
+    let mut labels = Vec::new();
+    loop {
+        let len = *req.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        // question セクションでの名前の圧縮は扱わない
+        if len & 0xc0 != 0 {
+            return None;
+        }
+        let label = req.get(pos..pos + len)?;
+        labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+        pos += len;
+    }
+    let tail = req.get(pos..pos + 4)?;
+    Some(Question {
+        labels,
+        qtype: u16::from_be_bytes([tail[0], tail[1]]),
+        qclass: u16::from_be_bytes([tail[2], tail[3]]),
+        raw: &req[12..pos + 4],
+    })
+}
+
+fn encode_name(name: &str) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(name.len() + 2);
+    for label in name.split('.').filter(|l| !l.is_empty()) {
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+    buf
+}
+
+fn record(name: &[u8], rtype: u16, rdata: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(name.len() + 10 + rdata.len());
+    buf.extend_from_slice(name);
+    buf.extend_from_slice(&rtype.to_be_bytes());
+    buf.extend_from_slice(&CLASS_IN.to_be_bytes());
+    buf.extend_from_slice(&TTL.to_be_bytes());
+    buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    buf.extend_from_slice(rdata);
+    buf
+}
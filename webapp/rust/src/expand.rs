@@ -0,0 +1,156 @@
+//! 一覧 API の `?expand=` / `?fields=` による省略形式のレスポンス
+//!
+//! どちらも指定されなければ従来どおり、関連するユーザーやライブ配信を全て埋め込んだ配列を返す。
+//! どちらかが指定された場合は `{"data": [...], "included": {...}}` の形式で返す。
+//!
+//! - `expand`: 埋め込む関連 (カンマ区切り)。それ以外の関連は id のみを返し、
+//!   実体は `included` に id ごとに一度だけ入れる
+//! - `fields`: 各要素に含めるフィールド (カンマ区切り)。含めない関連は読み込まない
+
+use crate::Error;
+use axum::response::IntoResponse as _;
+use std::collections::HashSet;
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct ExpandQuery {
+    expand: Option<String>,
+    fields: Option<String>,
+}
+
+/// 関連をどう返すか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    /// 実体を埋め込む
+    Embed,
+    /// id のみを返し、実体は included に入れる
+    Id,
+    /// fields で除かれるので読み込まない
+    Omit,
+}
+
+/// 省略形式では id のみ、そうでなければ実体を返す関連
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(untagged)]
+pub enum Expandable<T> {
+    Id(i64),
+    Full(T),
+}
+
+#[derive(Debug, Default)]
+pub struct Expand {
+    /// false なら従来の形式
+    compact: bool,
+    expand: HashSet<String>,
+    /// None なら全てのフィールド
+    fields: Option<HashSet<String>>,
+}
+
+fn split(s: &str) -> impl Iterator<Item = &str> {
+    s.split(',').map(str::trim).filter(|name| !name.is_empty())
+}
+
+impl ExpandQuery {
+    /// `fields` は各要素のフィールド名、`relations` は expand に指定できる関連の名前
+    pub fn parse(&self, fields: &[&str], relations: &[&str]) -> Result<Expand, Error> {
+        let expand = split(self.expand.as_deref().unwrap_or_default())
+            .map(|name| {
+                if relations.contains(&name) {
+                    Ok(name.to_owned())
+                } else {
+                    Err(Error::BadRequest(
+                        format!("unknown relation in expand: {name}").into(),
+                    ))
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        let selected = self
+            .fields
+            .as_deref()
+            .map(|s| {
+                split(s)
+                    .map(|name| {
+                        if fields.contains(&name) {
+                            Ok(name.to_owned())
+                        } else {
+                            Err(Error::BadRequest(
+                                format!("unknown field in fields: {name}").into(),
+                            ))
+                        }
+                    })
+                    .collect::<Result<_, _>>()
+            })
+            .transpose()?;
+        Ok(Expand {
+            compact: self.expand.is_some() || self.fields.is_some(),
+            expand,
+            fields: selected,
+        })
+    }
+}
+
+impl Expand {
+    /// 従来の形式 (全て埋め込む)
+    pub fn full() -> Self {
+        Self::default()
+    }
+
+    /// 一覧の要素が直接持つ関連
+    pub fn relation(&self, name: &str) -> Relation {
+        if self
+            .fields
+            .as_ref()
+            .is_some_and(|fields| !fields.contains(name))
+        {
+            return Relation::Omit;
+        }
+        self.nested(name)
+    }
+
+    /// 関連の先の関連 (報告のライブコメントのユーザーなど)。fields の影響は受けない
+    pub fn nested(&self, name: &str) -> Relation {
+        if !self.compact || self.expand.contains(name) {
+            Relation::Embed
+        } else {
+            Relation::Id
+        }
+    }
+
+    pub fn respond<T, I>(
+        &self,
+        items: Vec<T>,
+        included: I,
+    ) -> Result<axum::response::Response, Error>
+    where
+        T: serde::Serialize,
+        I: serde::Serialize,
+    {
+        #[derive(serde::Serialize)]
+        struct Compact<T, I> {
+            data: Vec<T>,
+            included: I,
+        }
+
+        if !self.compact {
+            return Ok(axum::Json(items).into_response());
+        }
+        let Some(fields) = &self.fields else {
+            return Ok(axum::Json(Compact {
+                data: items,
+                included,
+            })
+            .into_response());
+        };
+        let data = items
+            .into_iter()
+            .map(|item| {
+                let mut value = serde_json::to_value(item)
+                    .map_err(|e| Error::InternalServerError(e.to_string()))?;
+                if let serde_json::Value::Object(object) = &mut value {
+                    object.retain(|key, _| fields.contains(key));
+                }
+                Ok(value)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(axum::Json(Compact { data, included }).into_response())
+    }
+}
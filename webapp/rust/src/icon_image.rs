@@ -0,0 +1,355 @@
+//! アップロードされたアイコン画像の検証とサムネイル生成
+
+use crate::Error;
+use image::ImageFormat;
+use std::sync::OnceLock;
+
+/// アップロードを受け付ける画像の最大バイト数
+pub const MAX_ICON_BYTES: usize = 1024 * 1024;
+/// アップロードを受け付ける画像の最大の幅・高さ
+pub const MAX_ICON_DIMENSION: u32 = 2048;
+/// 生成するサムネイルの一辺の長さ (GET /api/user/:username/icon?size= で指定できる値)
+pub const ICON_SIZES: [u32; 2] = [64, 256];
+const THUMBNAIL_JPEG_QUALITY: u8 = 85;
+
+/// アップロードを受け付ける画像の形式。icons.format に保存する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IconFormat {
+    Jpeg,
+    Png,
+    WebP,
+}
+
+impl IconFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpeg",
+            Self::Png => "png",
+            Self::WebP => "webp",
+        }
+    }
+
+    /// 保存するファイルの拡張子
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::WebP => "webp",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::WebP => "image/webp",
+        }
+    }
+}
+
+impl TryFrom<String> for IconFormat {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "jpeg" => Ok(Self::Jpeg),
+            "png" => Ok(Self::Png),
+            "webp" => Ok(Self::WebP),
+            _ => Err(format!("unknown icon format: {s}")),
+        }
+    }
+}
+
+/// `ISUCON13_ICON_STRIP_METADATA=1` なら元画像からも EXIF などのメタデータを取り除いて保存する。
+/// icon_hash は保存したバイト列の sha256 なので、メタデータを含む画像ではアップロードされた
+/// バイト列の sha256 と一致しなくなる。ベンチマーカーはこれが一致することを確かめるので既定では無効
+pub fn strip_original_metadata() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| std::env::var("ISUCON13_ICON_STRIP_METADATA").is_ok_and(|v| v == "1"))
+}
+
+#[derive(Debug)]
+pub struct ProcessedIcon {
+    pub format: IconFormat,
+    /// 保存する元画像。strip_original_metadata が無効ならアップロードされたバイト列のまま
+    pub original: Vec<u8>,
+    /// ICON_SIZES と同じ順の JPEG。再エンコードするので EXIF 等のメタデータは含まれない
+    pub thumbnails: Vec<(u32, Vec<u8>)>,
+}
+
+/// 画像として正しいか検証し、サムネイルを生成する。
+/// デコードとリサイズは重いので spawn_blocking から呼ぶこと。
+pub fn process(data: Vec<u8>) -> Result<ProcessedIcon, Error> {
+    if data.is_empty() {
+        return Err(Error::BadRequest("image is empty".into()));
+    }
+    if data.len() > MAX_ICON_BYTES {
+        return Err(Error::BadRequest(
+            format!("image must be at most {MAX_ICON_BYTES} bytes").into(),
+        ));
+    }
+
+    let (format, icon_format) = match image::guess_format(&data) {
+        Ok(ImageFormat::Jpeg) => (ImageFormat::Jpeg, IconFormat::Jpeg),
+        Ok(ImageFormat::Png) => (ImageFormat::Png, IconFormat::Png),
+        Ok(ImageFormat::WebP) => (ImageFormat::WebP, IconFormat::WebP),
+        _ => return Err(Error::BadRequest("image must be JPEG, PNG or WebP".into())),
+    };
+
+    // デコードする前にヘッダだけ読んでサイズを確認する
+    let (width, height) = image::io::Reader::with_format(std::io::Cursor::new(&data), format)
+        .into_dimensions()
+        .map_err(|e| Error::BadRequest(format!("invalid image: {e}").into()))?;
+    if width == 0 || height == 0 || width > MAX_ICON_DIMENSION || height > MAX_ICON_DIMENSION {
+        return Err(Error::BadRequest(
+            format!("image must be at most {MAX_ICON_DIMENSION}x{MAX_ICON_DIMENSION} pixels")
+                .into(),
+        ));
+    }
+
+    let decoded = image::load_from_memory_with_format(&data, format)
+        .map_err(|e| Error::BadRequest(format!("invalid image: {e}").into()))?;
+    let decoded = image::DynamicImage::ImageRgb8(decoded.to_rgb8());
+
+    let mut thumbnails = Vec::with_capacity(ICON_SIZES.len());
+    for size in ICON_SIZES {
+        let resized = decoded.resize_to_fill(size, size, image::imageops::FilterType::Triangle);
+        let mut buf = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buf, THUMBNAIL_JPEG_QUALITY)
+            .encode_image(&resized)
+            .map_err(|e| Error::InternalServerError(format!("failed to encode thumbnail: {e}")))?;
+        thumbnails.push((size, buf));
+    }
+
+    let original = if strip_original_metadata() {
+        strip_metadata(&data, icon_format)?
+    } else {
+        data
+    };
+
+    Ok(ProcessedIcon {
+        format: icon_format,
+        original,
+        thumbnails,
+    })
+}
+
+/// 画素データはそのままに、EXIF と XMP のメタデータを取り除く。
+/// JPEG の APP1 セグメント、PNG の eXIf チャンク、WebP の EXIF と XMP チャンクを落とし、
+/// それ以外のバイト列は変えない
+pub fn strip_metadata(data: &[u8], format: IconFormat) -> Result<Vec<u8>, Error> {
+    match format {
+        IconFormat::Jpeg => strip_jpeg(data),
+        IconFormat::Png => strip_png(data),
+        IconFormat::WebP => strip_webp(data),
+    }
+    .ok_or(Error::BadRequest("invalid image: broken container".into()))
+}
+
+fn strip_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    const APP1: u8 = 0xE1;
+    const SOS: u8 = 0xDA;
+    const EOI: u8 = 0xD9;
+
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        // マーカーの前には 0xFF の詰め物が入りうる
+        let mut marker_pos = pos + 1;
+        while *data.get(marker_pos)? == 0xFF {
+            marker_pos += 1;
+        }
+        let marker = data[marker_pos];
+        match marker {
+            // ここから先は画像データなのでそのまま残す
+            SOS | EOI => {
+                out.extend_from_slice(&data[pos..]);
+                return Some(out);
+            }
+            // 長さを持たないマーカー
+            0x01 | 0xD0..=0xD7 => {
+                out.extend_from_slice(&data[pos..=marker_pos]);
+                pos = marker_pos + 1;
+                continue;
+            }
+            _ => {}
+        }
+        let len = u16::from_be_bytes([*data.get(marker_pos + 1)?, *data.get(marker_pos + 2)?]);
+        if len < 2 {
+            return None;
+        }
+        let end = marker_pos + 1 + len as usize;
+        if end > data.len() {
+            return None;
+        }
+        if marker != APP1 {
+            out.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+    }
+}
+
+fn strip_png(data: &[u8]) -> Option<Vec<u8>> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    if !data.starts_with(SIGNATURE) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(SIGNATURE);
+    let mut pos = SIGNATURE.len();
+    while pos < data.len() {
+        let len = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?);
+        let chunk_type = data.get(pos + 4..pos + 8)?;
+        // 長さ、種類、データ、CRC
+        let end = pos.checked_add(12 + len as usize)?;
+        if end > data.len() {
+            return None;
+        }
+        if chunk_type != b"eXIf" {
+            out.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+    }
+    Some(out)
+}
+
+fn strip_webp(data: &[u8]) -> Option<Vec<u8>> {
+    /// VP8X チャンクのフラグのうち EXIF と XMP を含むことを表すビット
+    const VP8X_METADATA_FLAGS: u8 = 0x08 | 0x04;
+
+    if !data.starts_with(b"RIFF") || data.get(8..12)? != b"WEBP" {
+        return None;
+    }
+    let riff_size = u32::from_le_bytes(data.get(4..8)?.try_into().ok()?);
+    let riff_end = 8usize.checked_add(riff_size as usize)?;
+    if riff_end > data.len() {
+        return None;
+    }
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..12]);
+    let mut pos = 12;
+    while pos < riff_end {
+        let fourcc = data.get(pos..pos + 4)?;
+        let size = u32::from_le_bytes(data.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        // チャンクのデータは偶数バイトに揃えられる
+        let end = pos.checked_add(8 + size + (size & 1))?;
+        if end > riff_end {
+            return None;
+        }
+        match fourcc {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let flags_pos = out.len() + 8;
+                out.extend_from_slice(&data[pos..end]);
+                *out.get_mut(flags_pos)? &= !VP8X_METADATA_FLAGS;
+            }
+            _ => out.extend_from_slice(&data[pos..end]),
+        }
+        pos = end;
+    }
+    let riff_size = u32::try_from(out.len() - 8).ok()?;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(format: ImageFormat) -> Vec<u8> {
+        let image = image::DynamicImage::new_rgb8(4, 4);
+        let mut buf = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut buf, format).unwrap();
+        buf.into_inner()
+    }
+
+    #[test]
+    fn strips_jpeg_app1_segments() {
+        let jpeg = encode(ImageFormat::Jpeg);
+        assert_eq!(strip_metadata(&jpeg, IconFormat::Jpeg).unwrap(), jpeg);
+
+        // SOI の直後に EXIF (GPS を含みうる) の APP1 を差し込む
+        let payload = b"Exif\0\0GPS";
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        app1.extend_from_slice(payload);
+        let mut with_exif = jpeg[..2].to_vec();
+        with_exif.extend_from_slice(&app1);
+        with_exif.extend_from_slice(&jpeg[2..]);
+
+        let stripped = strip_metadata(&with_exif, IconFormat::Jpeg).unwrap();
+        assert_eq!(stripped, jpeg);
+        image::load_from_memory_with_format(&stripped, ImageFormat::Jpeg).unwrap();
+    }
+
+    #[test]
+    fn strips_png_exif_chunks() {
+        let png = encode(ImageFormat::Png);
+        assert_eq!(strip_metadata(&png, IconFormat::Png).unwrap(), png);
+
+        // IHDR (8 + 13 + 4 バイト) の後に eXIf を差し込む
+        let ihdr_end = 8 + 8 + 13 + 4;
+        let mut with_exif = png[..ihdr_end].to_vec();
+        with_exif.extend_from_slice(&4u32.to_be_bytes());
+        with_exif.extend_from_slice(b"eXIfMM\0*");
+        with_exif.extend_from_slice(&[0; 4]);
+        with_exif.extend_from_slice(&png[ihdr_end..]);
+
+        let stripped = strip_metadata(&with_exif, IconFormat::Png).unwrap();
+        assert_eq!(stripped, png);
+    }
+
+    fn riff(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut body = b"WEBP".to_vec();
+        for (fourcc, data) in chunks {
+            body.extend_from_slice(*fourcc);
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut out = b"RIFF".to_vec();
+        out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        out.extend_from_slice(&body);
+        out
+    }
+
+    #[test]
+    fn strips_webp_exif_and_xmp_chunks() {
+        let vp8x = |flags: u8| [flags, 0, 0, 0, 3, 0, 0, 3, 0, 0];
+        let with_metadata = riff(&[
+            (b"VP8X", &vp8x(0x10 | 0x08 | 0x04)),
+            (b"VP8L", b"pixels!"),
+            (b"EXIF", b"MM\0*gps"),
+            (b"XMP ", b"<x/>"),
+        ]);
+        let expected = riff(&[(b"VP8X", &vp8x(0x10)), (b"VP8L", b"pixels!")]);
+        assert_eq!(
+            strip_metadata(&with_metadata, IconFormat::WebP).unwrap(),
+            expected
+        );
+        assert_eq!(
+            strip_metadata(&expected, IconFormat::WebP).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_containers() {
+        let jpeg = encode(ImageFormat::Jpeg);
+        assert!(strip_metadata(&jpeg[..10], IconFormat::Jpeg).is_err());
+        let png = encode(ImageFormat::Png);
+        assert!(strip_metadata(&png[..20], IconFormat::Png).is_err());
+        let webp = riff(&[(b"VP8L", b"pixels!")]);
+        assert!(strip_metadata(&webp[..webp.len() - 2], IconFormat::WebP).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+use crate::icon_image::IconFormat;
+use async_session::async_trait;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio::io::{AsyncRead, AsyncWriteExt as _};
+
+pub type IconReader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// アイコン画像の保存先。
+///
+/// `icon_hash` は元画像の sha256 (16進文字列) で、icons テーブルに保存されているものと同じ値。
+/// `size` が None なら元画像、Some ならその大きさのサムネイルを指す。
+/// `format` は画像の形式で、元画像は icons.format、サムネイルは常に JPEG。
+///
+/// 書き込みは [`IconStore::stage`] と [`StagedIcon::publish`] の 2 段階で行う。
+/// icons の更新をコミットしてから publish することで、ロールバックされた画像が配信されないようにする。
+#[async_trait]
+pub trait IconStore: Send + Sync {
+    /// 画像を書き込むが、publish するまでは get から見えない
+    async fn stage(
+        &self,
+        user_name: &str,
+        icon_hash: &str,
+        format: IconFormat,
+        size: Option<u32>,
+        image: &[u8],
+    ) -> std::io::Result<Box<dyn StagedIcon>>;
+    /// 保存されていなければ None
+    async fn get(
+        &self,
+        user_name: &str,
+        icon_hash: &str,
+        format: IconFormat,
+        size: Option<u32>,
+    ) -> std::io::Result<Option<IconReader>>;
+    /// /api/initialize で呼ばれる
+    fn clear(&self) {}
+}
+
+/// stage で書き込んだ画像。publish せずに drop すると破棄される
+#[async_trait]
+pub trait StagedIcon: Send {
+    async fn publish(self: Box<Self>) -> std::io::Result<()>;
+}
+
+/// `ISUCON13_ICON_STORE` の値から保存先を選択する。
+///
+/// - `local` (デフォルト): `{base}/{user_name}.{ext}` (nginx から直接配信する場合)
+/// - `hash`: `{base}/{icon_hash}.{ext}`
+/// - `memory`: プロセス内のメモリ
+///
+/// `ext` は元画像の形式に合わせた拡張子 (jpg, png, webp) で、
+/// サムネイルはそれぞれ `{user_name}_{size}.jpg`, `{icon_hash}_{size}.jpg` になる。
+pub fn build_icon_store(default_base_path: &str) -> Arc<dyn IconStore> {
+    let base_path = PathBuf::from(
+        std::env::var("ISUCON13_ICON_BASE_PATH").unwrap_or_else(|_| default_base_path.to_owned()),
+    );
+    match std::env::var("ISUCON13_ICON_STORE")
+        .unwrap_or_default()
+        .as_str()
+    {
+        "" | "local" => Arc::new(LocalIconStore { base_path }),
+        "hash" => Arc::new(ContentAddressedIconStore { base_path }),
+        "memory" => Arc::new(InMemoryIconStore::default()),
+        other => panic!("unknown ISUCON13_ICON_STORE: {other}"),
+    }
+}
+
+/// 一時ファイルに書き込んだ画像。publish で rename することで、読み込み側が書きかけのファイルを見ないようにする
+struct StagedFile {
+    /// publish 済みなら None
+    tmp_path: Option<PathBuf>,
+    path: PathBuf,
+}
+
+impl StagedFile {
+    async fn write(path: PathBuf, data: &[u8]) -> std::io::Result<Self> {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no file name"))?;
+        let tmp_path = dir.join(format!(
+            ".{}.{}.tmp",
+            file_name.to_string_lossy(),
+            uuid::Uuid::new_v4()
+        ));
+        // 途中で失敗した場合は Drop で一時ファイルを消す
+        let staged = Self {
+            tmp_path: Some(tmp_path),
+            path,
+        };
+        let mut file = tokio::fs::File::create(staged.tmp_path.as_ref().unwrap()).await?;
+        file.write_all(data).await?;
+        file.sync_all().await?;
+        Ok(staged)
+    }
+}
+
+#[async_trait]
+impl StagedIcon for StagedFile {
+    async fn publish(mut self: Box<Self>) -> std::io::Result<()> {
+        let tmp_path = self.tmp_path.take().unwrap();
+        let result = tokio::fs::rename(&tmp_path, &self.path).await;
+        if result.is_err() {
+            self.tmp_path = Some(tmp_path);
+        }
+        result
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        if let Some(tmp_path) = &self.tmp_path {
+            let _ = std::fs::remove_file(tmp_path);
+        }
+    }
+}
+
+/// 書き込む必要のない画像
+struct AlreadyStored;
+
+#[async_trait]
+impl StagedIcon for AlreadyStored {
+    async fn publish(self: Box<Self>) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn file_name(key: &str, format: IconFormat, size: Option<u32>) -> String {
+    let ext = format.extension();
+    match size {
+        Some(size) => format!("{key}_{size}.{ext}"),
+        None => format!("{key}.{ext}"),
+    }
+}
+
+async fn open_if_exists(path: &Path) -> std::io::Result<Option<IconReader>> {
+    match tokio::fs::File::open(path).await {
+        Ok(file) => Ok(Some(Box::new(file))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub struct LocalIconStore {
+    base_path: PathBuf,
+}
+
+#[async_trait]
+impl IconStore for LocalIconStore {
+    async fn stage(
+        &self,
+        user_name: &str,
+        _icon_hash: &str,
+        format: IconFormat,
+        size: Option<u32>,
+        image: &[u8],
+    ) -> std::io::Result<Box<dyn StagedIcon>> {
+        let path = self.base_path.join(file_name(user_name, format, size));
+        Ok(Box::new(StagedFile::write(path, image).await?))
+    }
+
+    async fn get(
+        &self,
+        user_name: &str,
+        _icon_hash: &str,
+        format: IconFormat,
+        size: Option<u32>,
+    ) -> std::io::Result<Option<IconReader>> {
+        open_if_exists(&self.base_path.join(file_name(user_name, format, size))).await
+    }
+}
+
+/// 同じ画像は同じファイルになるので、同じ内容の再アップロードでは書き込まない
+pub struct ContentAddressedIconStore {
+    base_path: PathBuf,
+}
+
+#[async_trait]
+impl IconStore for ContentAddressedIconStore {
+    async fn stage(
+        &self,
+        _user_name: &str,
+        icon_hash: &str,
+        format: IconFormat,
+        size: Option<u32>,
+        image: &[u8],
+    ) -> std::io::Result<Box<dyn StagedIcon>> {
+        let path = self.base_path.join(file_name(icon_hash, format, size));
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(Box::new(AlreadyStored));
+        }
+        Ok(Box::new(StagedFile::write(path, image).await?))
+    }
+
+    async fn get(
+        &self,
+        _user_name: &str,
+        icon_hash: &str,
+        format: IconFormat,
+        size: Option<u32>,
+    ) -> std::io::Result<Option<IconReader>> {
+        open_if_exists(&self.base_path.join(file_name(icon_hash, format, size))).await
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryIconStore {
+    /// ContentAddressedIconStore と同じファイル名をキーにする
+    icons: Arc<RwLock<HashMap<String, Arc<[u8]>>>>,
+}
+
+struct StagedMemory {
+    icons: Arc<RwLock<HashMap<String, Arc<[u8]>>>>,
+    key: String,
+    image: Arc<[u8]>,
+}
+
+#[async_trait]
+impl StagedIcon for StagedMemory {
+    async fn publish(self: Box<Self>) -> std::io::Result<()> {
+        self.icons.write().unwrap().insert(self.key, self.image);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl IconStore for InMemoryIconStore {
+    async fn stage(
+        &self,
+        _user_name: &str,
+        icon_hash: &str,
+        format: IconFormat,
+        size: Option<u32>,
+        image: &[u8],
+    ) -> std::io::Result<Box<dyn StagedIcon>> {
+        Ok(Box::new(StagedMemory {
+            icons: self.icons.clone(),
+            key: file_name(icon_hash, format, size),
+            image: image.into(),
+        }))
+    }
+
+    async fn get(
+        &self,
+        _user_name: &str,
+        icon_hash: &str,
+        format: IconFormat,
+        size: Option<u32>,
+    ) -> std::io::Result<Option<IconReader>> {
+        let image = self
+            .icons
+            .read()
+            .unwrap()
+            .get(&file_name(icon_hash, format, size))
+            .cloned();
+        Ok(image.map(|image| Box::new(std::io::Cursor::new(image)) as IconReader))
+    }
+
+    fn clear(&self) {
+        self.icons.write().unwrap().clear();
+    }
+}
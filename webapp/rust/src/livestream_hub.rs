@@ -0,0 +1,78 @@
+//! ライブ配信ごとの新着イベント (ライブコメント等) をプロセス内で配信する
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// 購読者ごとに保持する未読イベント数の上限。これを超えて遅れた購読者は切断される
+const CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Livecomment,
+    Reaction,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Livecomment => "livecomment",
+            Self::Reaction => "reaction",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LivestreamEvent {
+    pub kind: EventKind,
+    /// ライブコメントの id など。種類ごとに単調増加する
+    pub id: i64,
+    /// API のレスポンスと同じ JSON
+    pub data: String,
+}
+
+#[derive(Clone, Default)]
+pub struct LivestreamHub {
+    /// livestream id to sender
+    channels: Arc<Mutex<HashMap<i64, broadcast::Sender<Arc<LivestreamEvent>>>>>,
+}
+
+impl LivestreamHub {
+    pub fn subscribe(&self, livestream_id: i64) -> broadcast::Receiver<Arc<LivestreamEvent>> {
+        self.channels
+            .lock()
+            .unwrap()
+            .entry(livestream_id)
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// 購読者がいなければシリアライズもしない
+    pub fn publish<T: serde::Serialize>(
+        &self,
+        livestream_id: i64,
+        kind: EventKind,
+        id: i64,
+        payload: &T,
+    ) {
+        let sender = {
+            let mut channels = self.channels.lock().unwrap();
+            let Some(sender) = channels.get(&livestream_id) else {
+                return;
+            };
+            if sender.receiver_count() == 0 {
+                channels.remove(&livestream_id);
+                return;
+            }
+            sender.clone()
+        };
+        let data = match serde_json::to_string(payload) {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::error!("failed to serialize {} event: {}", kind.as_str(), e);
+                return;
+            }
+        };
+        let _ = sender.send(Arc::new(LivestreamEvent { kind, id, data }));
+    }
+}
@@ -2,21 +2,31 @@ use async_session::{async_trait, CookieStore, SessionStore};
 use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum_extra::extract::cookie::SignedCookieJar;
-use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use chrono::Utc;
 use core::hash::Hash;
 use moka::future::Cache;
 use sha2::Digest;
-use sqlx::mysql::{MySqlConnection, MySqlPool};
+use sqlx::mysql::MySqlConnection;
 use sqlx::prelude::FromRow;
 use sqlx::QueryBuilder;
 use std::borrow::Cow;
 use std::collections::hash_map::RandomState;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::Read;
 use std::sync::OnceLock;
 use uuid::Uuid;
 
+mod dns;
+mod expand;
+mod icon_image;
+mod icon_store;
+mod livestream_hub;
+mod metrics;
+mod ngword;
+mod pagination;
+mod slot_ledger;
+
 const DEFAULT_SESSION_ID_KEY: &str = "SESSIONID";
 const DEFUALT_SESSION_EXPIRES_KEY: &str = "EXPIRES";
 const DEFAULT_USER_ID_KEY: &str = "USERID";
@@ -24,6 +34,7 @@ const DEFAULT_USERNAME_KEY: &str = "USERNAME";
 const FALLBACK_IMAGE: &str = "../img/NoImage.jpg";
 
 #[derive(Debug, thiserror::Error)]
+#[allow(clippy::enum_variant_names)]
 enum Error {
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
@@ -45,6 +56,8 @@ enum Error {
     NotFound(Cow<'static, str>),
     #[error("{0}")]
     InternalServerError(String),
+    #[error("DNS error: {0}")]
+    Dns(String),
 }
 impl axum::response::IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
@@ -62,7 +75,8 @@ impl axum::response::IntoResponse for Error {
             | Self::Sqlx(_)
             | Self::Bcrypt(_)
             | Self::AsyncSession(_)
-            | Self::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            | Self::InternalServerError(_)
+            | Self::Dns(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
         tracing::error!("{}", self);
@@ -83,11 +97,88 @@ where
     V: Send + Sync + Clone + 'static,
 {
     fn get_cache(&self) -> &Cache<K, V, RandomState>;
-    async fn get(&self, tx: &mut MySqlConnection, key: K) -> V;
-    async fn get_or_insert(&self, tx: &mut MySqlConnection, key: K) -> V {
-        self.get_cache()
-            .get_with(key.clone(), self.get(tx, key.clone()))
+    fn metrics(&self) -> &metrics::CacheMetrics;
+    async fn get(&self, tx: &mut MySqlConnection, key: K) -> Result<V, Error>;
+    /// 全てのキーについて値を返す。既定では 1 件ずつ get する
+    async fn get_many(&self, tx: &mut MySqlConnection, keys: &[K]) -> Result<HashMap<K, V>, Error> {
+        let mut values = HashMap::with_capacity(keys.len());
+        for key in keys {
+            let value = self.get(&mut *tx, key.clone()).await?;
+            values.insert(key.clone(), value);
+        }
+        Ok(values)
+    }
+    /// 失敗した読み込みはキャッシュされない。同じキーへの同時呼び出しは 1 回の読み込みを共有する
+    async fn get_or_insert(&self, tx: &mut MySqlConnection, key: K) -> Result<V, Error> {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        // 他の呼び出し元の読み込み結果を受け取った場合もヒットとして数える
+        let loaded = AtomicBool::new(false);
+        let load = async {
+            loaded.store(true, Ordering::Relaxed);
+            self.metrics().record_miss();
+            let start = std::time::Instant::now();
+            let res = self.get(tx, key.clone()).await;
+            self.metrics().record_load(start.elapsed());
+            res
+        };
+        let entry = self
+            .get_cache()
+            .entry(key.clone())
+            .or_try_insert_with(load)
             .await
+            .map_err(|e| {
+                // 読み込みを共有した他の呼び出し元がまだ参照している場合は取り出せない
+                std::sync::Arc::try_unwrap(e)
+                    .unwrap_or_else(|e| Error::InternalServerError(e.to_string()))
+            })?;
+        if !loaded.load(Ordering::Relaxed) {
+            self.metrics().record_hit();
+        }
+        Ok(entry.into_value())
+    }
+    /// キャッシュにないキーだけを get_many でまとめて読み込む。重複したキーは 1 回だけ読み込む。
+    /// 読み込んだ値は entry 経由で入れるので、その間に get_or_insert で入った値や
+    /// 読み込み中の値があればそちらを優先し、直接 insert して上書きすることはない
+    async fn get_or_insert_many(
+        &self,
+        tx: &mut MySqlConnection,
+        keys: &[K],
+    ) -> Result<HashMap<K, V>, Error> {
+        let mut values = HashMap::with_capacity(keys.len());
+        let mut missing = Vec::new();
+        let mut seen = std::collections::HashSet::with_capacity(keys.len());
+        for key in keys {
+            if !seen.insert(key) {
+                continue;
+            }
+            match self.get_cache().get(key).await {
+                Some(value) => {
+                    self.metrics().record_hit();
+                    values.insert(key.clone(), value);
+                }
+                None => {
+                    self.metrics().record_miss();
+                    missing.push(key.clone());
+                }
+            }
+        }
+        if missing.is_empty() {
+            return Ok(values);
+        }
+
+        let start = std::time::Instant::now();
+        let loaded = self.get_many(tx, &missing).await?;
+        self.metrics().record_load(start.elapsed());
+        for (key, value) in loaded {
+            let entry = self
+                .get_cache()
+                .entry(key.clone())
+                .or_insert_with(async move { value })
+                .await;
+            values.insert(key, entry.into_value());
+        }
+        Ok(values)
     }
     async fn invalidate(&self, key: &K) {
         self.get_cache().invalidate(key).await;
@@ -97,16 +188,44 @@ where
     }
 }
 
+const DEFAULT_CACHE_CAPACITY: u64 = 1000;
+
+/// `ISUCON13_CACHE_{NAME}_CAPACITY` (エントリ数), `ISUCON13_CACHE_{NAME}_TTL_SECONDS` で容量と TTL を設定する。
+/// TTL は未指定なら無期限。
+fn build_cache<V>(name: &'static str) -> (Cache<i64, V>, std::sync::Arc<metrics::CacheMetrics>)
+where
+    V: Send + Sync + Clone + 'static,
+{
+    let env_prefix = format!("ISUCON13_CACHE_{}", name.to_ascii_uppercase());
+    let capacity = std::env::var(format!("{env_prefix}_CAPACITY"))
+        .ok()
+        .map(|v| v.parse().expect("cache capacity must be an integer"))
+        .unwrap_or(DEFAULT_CACHE_CAPACITY);
+    let ttl = std::env::var(format!("{env_prefix}_TTL_SECONDS"))
+        .ok()
+        .map(|v| std::time::Duration::from_secs(v.parse().expect("cache TTL must be an integer")));
+
+    let metrics = std::sync::Arc::new(metrics::CacheMetrics::new(name));
+    let listener_metrics = metrics.clone();
+    let mut builder = Cache::builder()
+        .max_capacity(capacity)
+        .eviction_listener(move |_, _, cause| listener_metrics.record_eviction(cause));
+    if let Some(ttl) = ttl {
+        builder = builder.time_to_live(ttl);
+    }
+    (builder.build(), metrics)
+}
+
 #[derive(Clone)]
 struct UserCache {
     cache: Cache<i64, Option<User>>,
+    metrics: std::sync::Arc<metrics::CacheMetrics>,
 }
 
 impl UserCache {
     fn new() -> Self {
-        Self {
-            cache: Cache::new(1000),
-        }
+        let (cache, metrics) = build_cache("user");
+        Self { cache, metrics }
     }
 }
 
@@ -115,32 +234,59 @@ impl MySqlResultCache<i64, Option<User>> for UserCache {
     fn get_cache(&self) -> &Cache<i64, Option<User>> {
         &self.cache
     }
-    async fn get(&self, tx: &mut MySqlConnection, user_id: i64) -> Option<User> {
+    fn metrics(&self) -> &metrics::CacheMetrics {
+        &self.metrics
+    }
+    async fn get(&self, tx: &mut MySqlConnection, user_id: i64) -> Result<Option<User>, Error> {
         let res: Option<UserModel> = sqlx::query_as("SELECT * FROM users WHERE id = ?")
             .bind(user_id)
             .fetch_optional(&mut *tx)
-            .await
-            .unwrap();
+            .await?;
 
         if let Some(user_model) = res {
-            Some(fill_user_response(&mut *tx, user_model).await.unwrap())
+            Ok(Some(fill_user_response(&mut *tx, user_model).await?))
         } else {
-            None
+            Ok(None)
         }
     }
+    async fn get_many(
+        &self,
+        tx: &mut MySqlConnection,
+        user_ids: &[i64],
+    ) -> Result<HashMap<i64, Option<User>>, Error> {
+        let mut users: HashMap<i64, Option<User>> =
+            user_ids.iter().map(|&user_id| (user_id, None)).collect();
+        if user_ids.is_empty() {
+            return Ok(users);
+        }
+
+        let mut query_builder = QueryBuilder::new("SELECT * FROM users WHERE id IN (");
+        let mut separated = query_builder.separated(", ");
+        for user_id in user_ids {
+            separated.push_bind(user_id);
+        }
+        separated.push_unseparated(")");
+        let user_models: Vec<UserModel> =
+            query_builder.build_query_as().fetch_all(&mut *tx).await?;
+
+        for user in fill_user_responses(&mut *tx, user_models).await? {
+            users.insert(user.id, Some(user));
+        }
+        Ok(users)
+    }
 }
 
 #[derive(Clone)]
 struct TagsCache {
     /// livestream id to tags
     cache: Cache<i64, Vec<Tag>>,
+    metrics: std::sync::Arc<metrics::CacheMetrics>,
 }
 
 impl TagsCache {
     fn new() -> Self {
-        Self {
-            cache: Cache::new(1000),
-        }
+        let (cache, metrics) = build_cache("tags");
+        Self { cache, metrics }
     }
 }
 
@@ -149,8 +295,11 @@ impl MySqlResultCache<i64, Vec<Tag>> for TagsCache {
     fn get_cache(&self) -> &Cache<i64, Vec<Tag>> {
         &self.cache
     }
+    fn metrics(&self) -> &metrics::CacheMetrics {
+        &self.metrics
+    }
 
-    async fn get(&self, tx: &mut MySqlConnection, livestream_id: i64) -> Vec<Tag> {
+    async fn get(&self, tx: &mut MySqlConnection, livestream_id: i64) -> Result<Vec<Tag>, Error> {
         let query = r#"
             SELECT t.*
             FROM tags t
@@ -160,16 +309,89 @@ impl MySqlResultCache<i64, Vec<Tag>> for TagsCache {
         let tag_models: Vec<TagModel> = sqlx::query_as(query)
             .bind(livestream_id)
             .fetch_all(&mut *tx)
-            .await
-            .unwrap();
+            .await?;
 
-        tag_models
+        Ok(tag_models
             .into_iter()
             .map(|tag_model| Tag {
                 id: tag_model.id,
                 name: tag_model.name,
             })
-            .collect()
+            .collect())
+    }
+    async fn get_many(
+        &self,
+        tx: &mut MySqlConnection,
+        livestream_ids: &[i64],
+    ) -> Result<HashMap<i64, Vec<Tag>>, Error> {
+        let mut tags = fill_tags_for_livestreams(tx, livestream_ids).await?;
+        for livestream_id in livestream_ids {
+            tags.entry(*livestream_id).or_default();
+        }
+        Ok(tags)
+    }
+}
+
+#[derive(Clone)]
+struct CollaboratorsCache {
+    /// livestream id to collaborator user ids
+    cache: Cache<i64, Vec<i64>>,
+    metrics: std::sync::Arc<metrics::CacheMetrics>,
+}
+
+impl CollaboratorsCache {
+    fn new() -> Self {
+        let (cache, metrics) = build_cache("collaborators");
+        Self { cache, metrics }
+    }
+}
+
+#[async_trait]
+impl MySqlResultCache<i64, Vec<i64>> for CollaboratorsCache {
+    fn get_cache(&self) -> &Cache<i64, Vec<i64>> {
+        &self.cache
+    }
+    fn metrics(&self) -> &metrics::CacheMetrics {
+        &self.metrics
+    }
+    async fn get(&self, tx: &mut MySqlConnection, livestream_id: i64) -> Result<Vec<i64>, Error> {
+        Ok(sqlx::query_scalar(
+            "SELECT user_id FROM livestream_collaborators WHERE livestream_id = ? ORDER BY id",
+        )
+        .bind(livestream_id)
+        .fetch_all(&mut *tx)
+        .await?)
+    }
+    async fn get_many(
+        &self,
+        tx: &mut MySqlConnection,
+        livestream_ids: &[i64],
+    ) -> Result<HashMap<i64, Vec<i64>>, Error> {
+        let mut collaborators: HashMap<i64, Vec<i64>> = livestream_ids
+            .iter()
+            .map(|&livestream_id| (livestream_id, Vec::new()))
+            .collect();
+        if livestream_ids.is_empty() {
+            return Ok(collaborators);
+        }
+
+        let mut query_builder = QueryBuilder::new(
+            "SELECT livestream_id, user_id FROM livestream_collaborators WHERE livestream_id IN (",
+        );
+        let mut separated = query_builder.separated(", ");
+        for livestream_id in livestream_ids {
+            separated.push_bind(livestream_id);
+        }
+        separated.push_unseparated(") ORDER BY id");
+        let rows: Vec<(i64, i64)> = query_builder.build_query_as().fetch_all(&mut *tx).await?;
+
+        for (livestream_id, user_id) in rows {
+            collaborators
+                .entry(livestream_id)
+                .or_default()
+                .push(user_id);
+        }
+        Ok(collaborators)
     }
 }
 
@@ -177,13 +399,13 @@ impl MySqlResultCache<i64, Vec<Tag>> for TagsCache {
 struct UserIdToLivestreamsCache {
     /// user id to models
     cache: Cache<i64, Vec<LivestreamModel>>,
+    metrics: std::sync::Arc<metrics::CacheMetrics>,
 }
 
 impl UserIdToLivestreamsCache {
     fn new() -> Self {
-        Self {
-            cache: Cache::new(1000),
-        }
+        let (cache, metrics) = build_cache("user_livestreams");
+        Self { cache, metrics }
     }
 }
 
@@ -192,12 +414,20 @@ impl MySqlResultCache<i64, Vec<LivestreamModel>> for UserIdToLivestreamsCache {
     fn get_cache(&self) -> &Cache<i64, Vec<LivestreamModel>> {
         &self.cache
     }
-    async fn get(&self, tx: &mut MySqlConnection, user_id: i64) -> Vec<LivestreamModel> {
-        sqlx::query_as("SELECT * FROM livestreams WHERE user_id = ?")
-            .bind(user_id)
-            .fetch_all(&mut *tx)
-            .await
-            .unwrap()
+    fn metrics(&self) -> &metrics::CacheMetrics {
+        &self.metrics
+    }
+    async fn get(
+        &self,
+        tx: &mut MySqlConnection,
+        user_id: i64,
+    ) -> Result<Vec<LivestreamModel>, Error> {
+        Ok(
+            sqlx::query_as("SELECT * FROM livestreams WHERE user_id = ?")
+                .bind(user_id)
+                .fetch_all(&mut *tx)
+                .await?,
+        )
     }
 }
 
@@ -205,13 +435,13 @@ impl MySqlResultCache<i64, Vec<LivestreamModel>> for UserIdToLivestreamsCache {
 struct LivestreamCache {
     /// livestream id to model
     cache: Cache<i64, Option<LivestreamModel>>,
+    metrics: std::sync::Arc<metrics::CacheMetrics>,
 }
 
 impl LivestreamCache {
     fn new() -> Self {
-        Self {
-            cache: Cache::new(1000),
-        }
+        let (cache, metrics) = build_cache("livestream");
+        Self { cache, metrics }
     }
 }
 
@@ -220,24 +450,119 @@ impl MySqlResultCache<i64, Option<LivestreamModel>> for LivestreamCache {
     fn get_cache(&self) -> &Cache<i64, Option<LivestreamModel>> {
         &self.cache
     }
-    async fn get(&self, tx: &mut MySqlConnection, livestream_id: i64) -> Option<LivestreamModel> {
-        sqlx::query_as("SELECT * FROM livestreams WHERE id = ?")
+    fn metrics(&self) -> &metrics::CacheMetrics {
+        &self.metrics
+    }
+    async fn get(
+        &self,
+        tx: &mut MySqlConnection,
+        livestream_id: i64,
+    ) -> Result<Option<LivestreamModel>, Error> {
+        Ok(sqlx::query_as("SELECT * FROM livestreams WHERE id = ?")
             .bind(livestream_id)
             .fetch_optional(&mut *tx)
-            .await
-            .unwrap()
+            .await?)
+    }
+    async fn get_many(
+        &self,
+        tx: &mut MySqlConnection,
+        livestream_ids: &[i64],
+    ) -> Result<HashMap<i64, Option<LivestreamModel>>, Error> {
+        let mut livestreams: HashMap<i64, Option<LivestreamModel>> = livestream_ids
+            .iter()
+            .map(|&livestream_id| (livestream_id, None))
+            .collect();
+        if livestream_ids.is_empty() {
+            return Ok(livestreams);
+        }
+
+        let mut query_builder = QueryBuilder::new("SELECT * FROM livestreams WHERE id IN (");
+        let mut separated = query_builder.separated(", ");
+        for livestream_id in livestream_ids {
+            separated.push_bind(livestream_id);
+        }
+        separated.push_unseparated(")");
+        let livestream_models: Vec<LivestreamModel> =
+            query_builder.build_query_as().fetch_all(&mut *tx).await?;
+
+        for livestream_model in livestream_models {
+            livestreams.insert(livestream_model.id, Some(livestream_model));
+        }
+        Ok(livestreams)
+    }
+}
+
+#[derive(Clone)]
+struct NgWordMatcherCache {
+    /// livestream id to compiled NG words
+    cache: Cache<i64, std::sync::Arc<ngword::NgWordMatcher>>,
+    metrics: std::sync::Arc<metrics::CacheMetrics>,
+}
+
+impl NgWordMatcherCache {
+    fn new() -> Self {
+        let (cache, metrics) = build_cache("ng_words");
+        Self { cache, metrics }
+    }
+}
+
+#[async_trait]
+impl MySqlResultCache<i64, std::sync::Arc<ngword::NgWordMatcher>> for NgWordMatcherCache {
+    fn get_cache(&self) -> &Cache<i64, std::sync::Arc<ngword::NgWordMatcher>> {
+        &self.cache
+    }
+    fn metrics(&self) -> &metrics::CacheMetrics {
+        &self.metrics
+    }
+    async fn get(
+        &self,
+        tx: &mut MySqlConnection,
+        livestream_id: i64,
+    ) -> Result<std::sync::Arc<ngword::NgWordMatcher>, Error> {
+        // 配信者自身が登録した、この配信と全ての配信向けの NG ワードを使う
+        let rules: Vec<(String, String)> = sqlx::query_as(
+            r#"
+            SELECT n.word, n.mode
+            FROM ng_words n
+            INNER JOIN livestreams l ON l.user_id = n.user_id
+            WHERE l.id = ? AND n.livestream_id IN (l.id, ?)
+            "#,
+        )
+        .bind(livestream_id)
+        .bind(DEFAULT_NG_WORDS_LIVESTREAM_ID)
+        .fetch_all(&mut *tx)
+        .await?;
+        let rules = rules
+            .into_iter()
+            .map(|(word, mode)| Ok((word, ngword::Mode::try_from(mode)?)))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(Error::InternalServerError)?;
+        let matcher =
+            ngword::NgWordMatcher::new(rules.iter().map(|(word, mode)| (word.as_str(), *mode)))
+                .map_err(Error::InternalServerError)?;
+        Ok(std::sync::Arc::new(matcher))
     }
 }
 
 #[derive(Clone)]
 struct AppState {
-    pool: MySqlPool,
+    pool: metrics::MeteredPool,
     key: axum_extra::extract::cookie::Key,
     user_cache: UserCache,
     /// livestream id to tags
     tags_cache: TagsCache,
+    /// livestream id to collaborator user ids
+    collaborators_cache: CollaboratorsCache,
     user_id_to_livestreams_cache: UserIdToLivestreamsCache,
     livestream_cache: LivestreamCache,
+    ng_word_matcher_cache: NgWordMatcherCache,
+    dns_registrar: std::sync::Arc<dyn dns::DnsRegistrar>,
+    /// 組み込み DNS サーバが応答するユーザ名
+    dns_names: dns::server::NameSet,
+    icon_store: std::sync::Arc<dyn icon_store::IconStore>,
+    http_metrics: std::sync::Arc<metrics::HttpMetrics>,
+    livestream_hub: livestream_hub::LivestreamHub,
+    slot_ledger: slot_ledger::SlotLedger,
 }
 impl axum::extract::FromRef<AppState> for axum_extra::extract::cookie::Key {
     fn from_ref(state: &AppState) -> Self {
@@ -245,6 +570,203 @@ impl axum::extract::FromRef<AppState> for axum_extra::extract::cookie::Key {
     }
 }
 
+#[derive(Debug, serde::Deserialize)]
+struct LivestreamPathParams {
+    livestream_id: i64,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct LivecommentPathParams {
+    livestream_id: i64,
+    livecomment_id: i64,
+}
+
+/// `/api/livestream/:livestream_id/...` の配信とログインユーザ。
+/// 全ての `/api/livestream/:livestream_id/...` はこれか、以下の権限を確かめる extractor を通す。
+/// 未ログインなら 403、セッションの期限切れなら 401、配信が存在しなければ 404 を返す
+struct LivestreamPath {
+    livestream_id: i64,
+    livestream: LivestreamModel,
+    user_id: i64,
+}
+
+/// ログインユーザ自身の `/api/livestream/:livestream_id/...` の配信。
+/// LivestreamPath の検証に加え、他の配信者の配信なら 403 を返す
+struct OwnedLivestreamPath {
+    livestream_id: i64,
+    #[allow(unused)]
+    livestream: LivestreamModel,
+    user_id: i64,
+}
+
+/// ログインユーザが配信者かコラボレーターである `/api/livestream/:livestream_id/...` の配信。
+/// LivestreamPath の検証に加え、どちらでもなければ 403 を返す
+struct ModeratedLivestreamPath {
+    livestream_id: i64,
+    #[allow(unused)]
+    livestream: LivestreamModel,
+    /// 配信者のユーザ ID。NG ワードはコラボレーターが登録したものも配信者のものとして扱う
+    owner_id: i64,
+}
+
+/// `/api/livestream/:livestream_id/livecomment/:livecomment_id/...` のライブコメント。
+/// LivestreamPath の検証に加え、ライブコメントが存在しないか別の配信のものなら 404 を返す
+struct LivecommentPath {
+    livestream_id: i64,
+    livecomment_id: i64,
+    #[allow(unused)]
+    livecomment: LivecommentModel,
+}
+
+/// セッションを検証し、ログインユーザの ID とパスの配信を返す
+async fn load_path_livestream(
+    parts: &mut axum::http::request::Parts,
+    state: &AppState,
+    livestream_id: i64,
+) -> Result<(i64, LivestreamModel), Error> {
+    let jar = SignedCookieJar::from_headers(&parts.headers, state.key.clone());
+    verify_user_session(&jar).await?;
+
+    let cookie = jar.get(DEFAULT_SESSION_ID_KEY).ok_or(Error::SessionError)?;
+    let sess = CookieStore::new()
+        .load_session(cookie.value().to_owned())
+        .await?
+        .ok_or(Error::SessionError)?;
+    let user_id: i64 = sess.get(DEFAULT_USER_ID_KEY).ok_or(Error::SessionError)?;
+
+    let mut conn = state.pool.acquire().await?;
+    let livestream = state
+        .livestream_cache
+        .get_or_insert(&mut conn, livestream_id)
+        .await?
+        .ok_or(Error::NotFound("livestream not found".into()))?;
+    Ok((user_id, livestream))
+}
+
+async fn path_params<T>(
+    parts: &mut axum::http::request::Parts,
+    state: &AppState,
+) -> Result<T, Error>
+where
+    T: serde::de::DeserializeOwned + Send,
+{
+    use axum::extract::FromRequestParts as _;
+
+    let Path(params) = Path::<T>::from_request_parts(parts, state)
+        .await
+        .map_err(|e| Error::BadRequest(e.body_text().into()))?;
+    Ok(params)
+}
+
+#[async_trait]
+impl axum::extract::FromRequestParts<AppState> for LivestreamPath {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let LivestreamPathParams { livestream_id } = path_params(parts, state).await?;
+        let (user_id, livestream) = load_path_livestream(parts, state, livestream_id).await?;
+        Ok(Self {
+            livestream_id,
+            livestream,
+            user_id,
+        })
+    }
+}
+
+#[async_trait]
+impl axum::extract::FromRequestParts<AppState> for OwnedLivestreamPath {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let LivestreamPath {
+            livestream_id,
+            livestream,
+            user_id,
+        } = LivestreamPath::from_request_parts(parts, state).await?;
+
+        if livestream.user_id != user_id {
+            return Err(Error::Forbidden(
+                "A streamer can't manage livestreams that other streamers own".into(),
+            ));
+        }
+        Ok(Self {
+            livestream_id,
+            livestream,
+            user_id,
+        })
+    }
+}
+
+#[async_trait]
+impl axum::extract::FromRequestParts<AppState> for ModeratedLivestreamPath {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let LivestreamPath {
+            livestream_id,
+            livestream,
+            user_id,
+        } = LivestreamPath::from_request_parts(parts, state).await?;
+
+        if livestream.user_id != user_id {
+            let mut conn = state.pool.acquire().await?;
+            let collaborators = state
+                .collaborators_cache
+                .get_or_insert(&mut conn, livestream_id)
+                .await?;
+            if !collaborators.contains(&user_id) {
+                return Err(Error::Forbidden(
+                    "only the streamer and collaborators can moderate this livestream".into(),
+                ));
+            }
+        }
+        Ok(Self {
+            livestream_id,
+            owner_id: livestream.user_id,
+            livestream,
+        })
+    }
+}
+
+#[async_trait]
+impl axum::extract::FromRequestParts<AppState> for LivecommentPath {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let LivecommentPathParams {
+            livestream_id,
+            livecomment_id,
+        } = path_params(parts, state).await?;
+        load_path_livestream(parts, state, livestream_id).await?;
+
+        let livecomment: LivecommentModel = sqlx::query_as(
+            "SELECT * FROM livecomments WHERE id = ? AND livestream_id = ? AND deleted_at IS NULL",
+        )
+        .bind(livecomment_id)
+        .bind(livestream_id)
+        .fetch_optional(&mut *state.pool.acquire().await?)
+        .await?
+        .ok_or(Error::NotFound("livecomment not found".into()))?;
+        Ok(Self {
+            livestream_id,
+            livecomment_id,
+            livecomment,
+        })
+    }
+}
+
 #[derive(Debug, serde::Serialize)]
 struct InitializeResponse {
     language: &'static str,
@@ -280,9 +802,15 @@ fn build_mysql_options() -> sqlx::mysql::MySqlConnectOptions {
 
 async fn initialize_handler(
     State(AppState {
+        pool,
         user_cache,
         tags_cache,
+        collaborators_cache,
         user_id_to_livestreams_cache,
+        ng_word_matcher_cache,
+        dns_names,
+        icon_store,
+        slot_ledger,
         ..
     }): State<AppState>,
 ) -> Result<axum::Json<InitializeResponse>, Error> {
@@ -292,7 +820,10 @@ async fn initialize_handler(
 
     user_cache.invalidate_all();
     tags_cache.invalidate_all();
+    collaborators_cache.invalidate_all();
     user_id_to_livestreams_cache.invalidate_all();
+    ng_word_matcher_cache.invalidate_all();
+    icon_store.clear();
 
     if !output.status.success() {
         return Err(Error::InternalServerError(format!(
@@ -302,9 +833,63 @@ async fn initialize_handler(
         )));
     }
 
+    let mut conn = pool.acquire().await?;
+    dns_names.reload(&mut conn).await?;
+    slot_ledger.reload(&mut conn).await?;
+
     Ok(axum::Json(InitializeResponse { language: "rust" }))
 }
 
+// メトリクス (Prometheus テキスト形式)
+// GET /metrics
+async fn metrics_handler(
+    State(AppState {
+        pool,
+        http_metrics,
+        user_cache,
+        tags_cache,
+        collaborators_cache,
+        user_id_to_livestreams_cache,
+        livestream_cache,
+        ng_word_matcher_cache,
+        ..
+    }): State<AppState>,
+) -> ([(axum::http::HeaderName, &'static str); 1], String) {
+    let mut out = String::new();
+    http_metrics.encode(&mut out);
+    pool.encode(&mut out);
+    metrics::encode_caches(
+        &mut out,
+        &[
+            (user_cache.metrics(), user_cache.cache.entry_count()),
+            (tags_cache.metrics(), tags_cache.cache.entry_count()),
+            (
+                collaborators_cache.metrics(),
+                collaborators_cache.cache.entry_count(),
+            ),
+            (
+                user_id_to_livestreams_cache.metrics(),
+                user_id_to_livestreams_cache.cache.entry_count(),
+            ),
+            (
+                livestream_cache.metrics(),
+                livestream_cache.cache.entry_count(),
+            ),
+            (
+                ng_word_matcher_cache.metrics(),
+                ng_word_matcher_cache.cache.entry_count(),
+            ),
+        ],
+    );
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4",
+        )],
+        out,
+    )
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     if std::env::var_os("RUST_LOG").is_none() {
@@ -316,6 +901,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .connect_with(build_mysql_options())
         .await
         .expect("failed to connect db");
+    let pool = metrics::MeteredPool::new(pool);
 
     const DEFAULT_SECRET: &[u8] = b"isucon13_session_cookiestore_defaultsecret";
     let secret = if let Ok(secret) = std::env::var("ISUCON13_SESSION_SECRETKEY") {
@@ -324,6 +910,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         DEFAULT_SECRET.to_owned()
     };
 
+    let dns_names = dns::server::NameSet::default();
+    if let Some(config) = dns::server::Config::from_env() {
+        dns_names.reload(&mut *pool.acquire().await?).await?;
+        dns::server::spawn(config, dns_names.clone()).await?;
+    }
+
+    let slot_ledger = slot_ledger::SlotLedger::new(slot_ledger::Config::from_env());
+    slot_ledger.reload(&mut *pool.acquire().await?).await?;
+
+    let state = AppState {
+        pool,
+        key: axum_extra::extract::cookie::Key::derive_from(&secret),
+        user_cache: UserCache::new(),
+        tags_cache: TagsCache::new(),
+        collaborators_cache: CollaboratorsCache::new(),
+        user_id_to_livestreams_cache: UserIdToLivestreamsCache::new(),
+        livestream_cache: LivestreamCache::new(),
+        ng_word_matcher_cache: NgWordMatcherCache::new(),
+        dns_registrar: dns::build_dns_registrar(),
+        dns_names,
+        icon_store: icon_store::build_icon_store(ICON_BASE_PATH),
+        http_metrics: Default::default(),
+        livestream_hub: Default::default(),
+        slot_ledger,
+    };
+
     let app = axum::Router::new()
         // 初期化
         .route("/api/initialize", axum::routing::post(initialize_handler))
@@ -339,6 +951,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             "/api/livestream/reservation",
             axum::routing::post(reserve_livestream_handler),
         )
+        // 予約枠の空き状況
+        .route(
+            "/api/livestream/reservation/availability",
+            axum::routing::get(get_reservation_availability_handler),
+        )
         // list livestream
         .route(
             "/api/livestream/search",
@@ -353,9 +970,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             axum::routing::get(get_user_livestreams_handler),
         )
         // get livestream
+        // 配信開始前のライブ配信のキャンセル、日時の変更
         .route(
             "/api/livestream/:livestream_id",
-            axum::routing::get(get_livestream_handler),
+            axum::routing::get(get_livestream_handler)
+                .delete(cancel_livestream_handler)
+                .patch(reschedule_livestream_handler),
         )
         // get polling livecomment timeline
         // ライブコメント投稿
@@ -363,19 +983,45 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             "/api/livestream/:livestream_id/livecomment",
             axum::routing::get(get_livecomments_handler).post(post_livecomment_handler),
         )
+        // 新着ライブコメントの受信 (Server-Sent Events)
+        .route(
+            "/api/livestream/:livestream_id/livecomment/stream",
+            axum::routing::get(stream_livecomments_handler),
+        )
+        // ライブコメント・リアクションの送受信 (WebSocket)
+        .route(
+            "/api/livestream/:livestream_id/ws",
+            axum::routing::get(livestream_ws_handler),
+        )
         .route(
             "/api/livestream/:livestream_id/reaction",
             axum::routing::get(get_reactions_handler).post(post_reaction_handler),
         )
-        // (配信者向け)ライブコメントの報告一覧取得API
+        // (配信者、コラボレーター向け)ライブコメントの報告一覧取得API
         .route(
             "/api/livestream/:livestream_id/report",
             axum::routing::get(get_livecomment_reports_handler),
         )
+        .route(
+            "/api/livestream/:livestream_id/report/:report_id",
+            axum::routing::patch(update_livecomment_report_handler),
+        )
         .route(
             "/api/livestream/:livestream_id/ngwords",
             axum::routing::get(get_ngwords),
         )
+        .route(
+            "/api/livestream/:livestream_id/ngwords/:word_id",
+            axum::routing::delete(delete_ngword_handler),
+        )
+        .route(
+            "/api/livestream/:livestream_id/ngwords/export",
+            axum::routing::get(export_ngwords_handler),
+        )
+        .route(
+            "/api/livestream/:livestream_id/ngwords/import",
+            axum::routing::post(import_ngwords_handler),
+        )
         // ライブコメント報告
         .route(
             "/api/livestream/:livestream_id/livecomment/:livecomment_id/report",
@@ -386,6 +1032,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             "/api/livestream/:livestream_id/moderate",
             axum::routing::post(moderate_handler),
         )
+        .route(
+            "/api/livestream/:livestream_id/moderate/preview",
+            axum::routing::get(moderate_preview_handler),
+        )
         // livestream_viewersにINSERTするため必要
         // ユーザ視聴開始 (viewer)
         .route(
@@ -401,12 +1051,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .route("/api/register", axum::routing::post(register_handler))
         .route("/api/login", axum::routing::post(login_handler))
         .route("/api/user/me", axum::routing::get(get_me_handler))
+        // 配信予約のコラボレーターに指定された通知など
+        .route(
+            "/api/user/me/notifications",
+            axum::routing::get(get_notifications_handler),
+        )
+        // 配信者の全ての配信に適用されるNGワード
+        .route(
+            "/api/user/me/ngwords",
+            axum::routing::get(get_default_ngwords_handler).post(post_default_ngword_handler),
+        )
+        .route(
+            "/api/user/me/ngwords/:word_id",
+            axum::routing::delete(delete_default_ngword_handler),
+        )
+        .route(
+            "/api/user/me/ngwords/export",
+            axum::routing::get(export_default_ngwords_handler),
+        )
+        .route(
+            "/api/user/me/ngwords/import",
+            axum::routing::post(import_default_ngwords_handler),
+        )
         // フロントエンドで、配信予約のコラボレーターを指定する際に必要
         .route("/api/user/:username", axum::routing::get(get_user_handler))
         .route(
             "/api/user/:username/statistics",
             axum::routing::get(get_user_statistics_handler),
         )
+        .route(
+            "/api/user/:username/icon",
+            axum::routing::get(get_icon_handler),
+        )
         .route("/api/icon", axum::routing::post(post_icon_handler))
         // stats
         // ライブ配信統計情報
@@ -416,15 +1092,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         )
         // 課金情報
         .route("/api/payment", axum::routing::get(get_payment_result))
-        .with_state(AppState {
-            pool,
-            key: axum_extra::extract::cookie::Key::derive_from(&secret),
-            user_cache: UserCache::new(),
-            tags_cache: TagsCache::new(),
-            user_id_to_livestreams_cache: UserIdToLivestreamsCache::new(),
-            livestream_cache: LivestreamCache::new(),
-        })
-        .layer(tower_http::trace::TraceLayer::new_for_http());
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.http_metrics.clone(),
+            metrics::track_http,
+        ));
+
+    // ISUCON13_METRICS_ADDRESS が設定されていれば /metrics は別のポートで公開する
+    let metrics_app = axum::Router::new().route("/metrics", axum::routing::get(metrics_handler));
+    let app = if let Ok(addr) = std::env::var("ISUCON13_METRICS_ADDRESS") {
+        let addr: std::net::SocketAddr = addr
+            .parse()
+            .expect("ISUCON13_METRICS_ADDRESS must be a socket address");
+        let server = axum::Server::bind(&addr)
+            .serve(metrics_app.with_state(state.clone()).into_make_service());
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                tracing::error!("metrics server error: {}", e);
+            }
+        });
+        app
+    } else {
+        app.merge(metrics_app)
+    }
+    .with_state(state)
+    .layer(tower_http::trace::TraceLayer::new_for_http());
 
     // HTTPサーバ起動
     if let Some(tcp_listener) = listenfd::ListenFd::from_env().take_tcp_listener(0)? {
@@ -488,7 +1179,7 @@ async fn get_streamer_theme_handler(
 
     let user_model: UserModel = sqlx::query_as("SELECT * FROM users WHERE name = ?")
         .bind(username)
-        .fetch_optional(&pool)
+        .fetch_optional(&mut *pool.acquire().await?)
         .await?
         .ok_or(Error::NotFound(
             "not found user that has the given username".into(),
@@ -509,6 +1200,9 @@ struct ReserveLivestreamRequest {
     thumbnail_url: String,
     start_at: i64,
     end_at: i64,
+    /// コラボレーターのユーザ名
+    #[serde(default)]
+    collaborators: Vec<String>,
 }
 
 #[derive(Debug, sqlx::FromRow, Clone)]
@@ -523,7 +1217,7 @@ struct LivestreamModel {
     end_at: i64,
 }
 
-#[derive(Debug, serde::Serialize)]
+#[derive(Debug, serde::Serialize, Clone)]
 struct Livestream {
     id: i64,
     owner: User,
@@ -532,17 +1226,55 @@ struct Livestream {
     playlist_url: String,
     thumbnail_url: String,
     tags: Vec<Tag>,
+    /// 配信予約時に指定されたコラボレーター
+    collaborators: Vec<User>,
     start_at: i64,
     end_at: i64,
+    status: LivestreamStatus,
 }
 
-impl From<(LivestreamModel, Vec<Tag>, User)> for Livestream {
-    fn from((livestream_model, tags, owner): (LivestreamModel, Vec<Tag>, User)) -> Self {
+/// 現在時刻と配信期間から求めるライブ配信の状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LivestreamStatus {
+    Upcoming,
+    Live,
+    Ended,
+}
+
+impl LivestreamStatus {
+    /// 配信期間は [start_at, end_at)
+    fn at(start_at: i64, end_at: i64, now: i64) -> Self {
+        if now < start_at {
+            Self::Upcoming
+        } else if now < end_at {
+            Self::Live
+        } else {
+            Self::Ended
+        }
+    }
+}
+
+impl From<(LivestreamModel, Vec<Tag>, User, Vec<User>)> for Livestream {
+    fn from(
+        (livestream_model, tags, owner, collaborators): (
+            LivestreamModel,
+            Vec<Tag>,
+            User,
+            Vec<User>,
+        ),
+    ) -> Self {
         Livestream {
+            status: LivestreamStatus::at(
+                livestream_model.start_at,
+                livestream_model.end_at,
+                Utc::now().timestamp(),
+            ),
             id: livestream_model.id,
             owner,
             title: livestream_model.title,
             tags,
+            collaborators,
             description: livestream_model.description,
             playlist_url: livestream_model.playlist_url,
             thumbnail_url: livestream_model.thumbnail_url,
@@ -552,13 +1284,103 @@ impl From<(LivestreamModel, Vec<Tag>, User)> for Livestream {
     }
 }
 
-#[derive(Debug, sqlx::FromRow)]
-struct ReservationSlotModel {
-    #[allow(unused)]
-    id: i64,
-    slot: i64,
-    start_at: i64,
-    end_at: i64,
+fn reservation_unavailable(config: &slot_ledger::Config, start_at: i64, end_at: i64) -> Error {
+    Error::BadRequest(
+        format!(
+            "予約期間 {} ~ {}に対して、予約区間 {} ~ {}が予約できません",
+            config.term_start_at, config.term_end_at, start_at, end_at
+        )
+        .into(),
+    )
+}
+
+/// 台帳で確保した予約枠の増減を、予約のトランザクションの中で reservation_slots に書き込む。
+/// 並列な日時変更どうしでデッドロックしないよう、行ロックは常に start_at の昇順に取る。
+/// 行ロックを持つ時間が短くなるよう、コミットの直前に呼ぶこと
+async fn write_reservation_slots(
+    tx: &mut MySqlConnection,
+    changes: &[slot_ledger::SlotChange],
+) -> sqlx::Result<()> {
+    let mut changes = changes.to_vec();
+    changes.sort_unstable();
+    for (start_at, end_at, delta) in changes {
+        sqlx::query(
+            "UPDATE reservation_slots SET slot = slot + ? WHERE start_at >= ? AND end_at <= ?",
+        )
+        .bind(delta)
+        .bind(start_at)
+        .bind(end_at)
+        .execute(&mut *tx)
+        .await?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ReservationAvailabilityQuery {
+    from: i64,
+    to: i64,
+}
+
+// [from, to) に含まれる予約枠ごとの残数
+// GET /api/livestream/reservation/availability
+async fn get_reservation_availability_handler(
+    State(AppState { slot_ledger, .. }): State<AppState>,
+    jar: SignedCookieJar,
+    Query(ReservationAvailabilityQuery { from, to }): Query<ReservationAvailabilityQuery>,
+) -> Result<axum::Json<Vec<slot_ledger::Slot>>, Error> {
+    verify_user_session(&jar).await?;
+
+    if from >= to {
+        return Err(Error::BadRequest("from must be before to".into()));
+    }
+
+    Ok(axum::Json(slot_ledger.range(from, to)))
+}
+
+/// 配信予約で指定されたコラボレーターのユーザ名をユーザ ID に変換する。重複は取り除く
+async fn resolve_collaborators(
+    tx: &mut MySqlConnection,
+    owner_id: i64,
+    usernames: &[String],
+) -> Result<Vec<i64>, Error> {
+    let mut seen = std::collections::HashSet::new();
+    let usernames: Vec<&str> = usernames
+        .iter()
+        .map(String::as_str)
+        .filter(|name| seen.insert(*name))
+        .collect();
+    if usernames.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut query_builder = QueryBuilder::new("SELECT name, id FROM users WHERE name IN (");
+    let mut separated = query_builder.separated(", ");
+    for name in &usernames {
+        separated.push_bind(*name);
+    }
+    separated.push_unseparated(")");
+    let user_ids: HashMap<String, i64> = query_builder
+        .build_query_as::<(String, i64)>()
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .collect();
+
+    usernames
+        .into_iter()
+        .map(|name| {
+            let &user_id = user_ids.get(name).ok_or_else(|| {
+                Error::BadRequest(format!("collaborator not found: {name}").into())
+            })?;
+            if user_id == owner_id {
+                return Err(Error::BadRequest(
+                    "配信者自身をコラボレーターに指定することはできません".into(),
+                ));
+            }
+            Ok(user_id)
+        })
+        .collect()
 }
 
 async fn reserve_livestream_handler(
@@ -566,7 +1388,9 @@ async fn reserve_livestream_handler(
         pool,
         user_cache,
         tags_cache,
+        collaborators_cache,
         user_id_to_livestreams_cache,
+        slot_ledger,
         ..
     }): State<AppState>,
     jar: SignedCookieJar,
@@ -585,73 +1409,17 @@ async fn reserve_livestream_handler(
         .ok_or(Error::SessionError)?;
     let user_id = sess.get(DEFAULT_USER_ID_KEY).ok_or(Error::SessionError)?;
 
-    let mut tx = pool.begin().await?;
-
-    // 2023/11/25 10:00からの１年間の期間内であるかチェック
-    let term_start_at = Utc.from_utc_datetime(
-        &NaiveDate::from_ymd_opt(2023, 11, 25)
-            .unwrap()
-            .and_hms_opt(1, 0, 0)
-            .unwrap(),
-    );
-    let term_end_at = Utc.from_utc_datetime(
-        &NaiveDate::from_ymd_opt(2024, 11, 25)
-            .unwrap()
-            .and_hms_opt(1, 0, 0)
-            .unwrap(),
-    );
-    let reserve_start_at = DateTime::from_timestamp(req.start_at, 0).unwrap();
-    let reserve_end_at = DateTime::from_timestamp(req.end_at, 0).unwrap();
-    if reserve_start_at >= term_end_at || reserve_end_at <= term_start_at {
-        return Err(Error::BadRequest("bad reservation time range".into()));
-    }
+    slot_ledger.config().check(req.start_at, req.end_at)?;
+    let collaborator_ids =
+        resolve_collaborators(&mut *pool.acquire().await?, user_id, &req.collaborators).await?;
 
     // 予約枠をみて、予約が可能か調べる
-    // NOTE: 並列な予約のoverbooking防止にFOR UPDATEが必要
-    let slots: Vec<ReservationSlotModel> = sqlx::query_as(
-        "SELECT * FROM reservation_slots WHERE start_at >= ? AND end_at <= ? FOR UPDATE",
-    )
-    .bind(req.start_at)
-    .bind(req.end_at)
-    .fetch_all(&mut *tx)
-    .await
-    .map_err(|e| {
-        tracing::warn!("予約枠一覧取得でエラー発生: {e:?}");
-        e
-    })?;
-    for slot in slots {
-        let count: i64 = sqlx::query_scalar(
-            "SELECT slot FROM reservation_slots WHERE start_at = ? AND end_at = ?",
-        )
-        .bind(slot.start_at)
-        .bind(slot.end_at)
-        .fetch_one(&mut *tx)
-        .await?;
-        tracing::info!(
-            "{} ~ {}予約枠の残数 = {}",
-            slot.start_at,
-            slot.end_at,
-            slot.slot
-        );
-        if count < 1 {
-            return Err(Error::BadRequest(
-                format!(
-                    "予約期間 {} ~ {}に対して、予約区間 {} ~ {}が予約できません",
-                    term_start_at.timestamp(),
-                    term_end_at.timestamp(),
-                    req.start_at,
-                    req.end_at
-                )
-                .into(),
-            ));
-        }
-    }
+    let slot_changes = [(req.start_at, req.end_at, -1)];
+    let hold = slot_ledger
+        .hold(&slot_changes)
+        .map_err(|_| reservation_unavailable(slot_ledger.config(), req.start_at, req.end_at))?;
 
-    sqlx::query("UPDATE reservation_slots SET slot = slot - 1 WHERE start_at >= ? AND end_at <= ?")
-        .bind(req.start_at)
-        .bind(req.end_at)
-        .execute(&mut *tx)
-        .await?;
+    let mut tx = pool.begin().await?;
 
     let rs = sqlx::query("INSERT INTO livestreams (user_id, title, description, playlist_url, thumbnail_url, start_at, end_at) VALUES(?, ?, ?, ?, ?, ?, ?)")
         .bind(user_id)
@@ -663,7 +1431,6 @@ async fn reserve_livestream_handler(
         .bind(req.end_at)
         .execute(&mut *tx)
         .await?;
-    user_id_to_livestreams_cache.invalidate(&user_id).await;
     let livestream_id = rs.last_insert_id() as i64;
 
     // タグ追加
@@ -675,8 +1442,27 @@ async fn reserve_livestream_handler(
             .await?;
     }
 
+    // コラボレーター追加と、それぞれへの通知
+    for &collaborator_id in &collaborator_ids {
+        sqlx::query("INSERT INTO livestream_collaborators (livestream_id, user_id) VALUES (?, ?)")
+            .bind(livestream_id)
+            .bind(collaborator_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    notify_collaborators(&mut tx, livestream_id, NotificationKind::CollaboratorAdded).await?;
+
+    write_reservation_slots(&mut tx, &slot_changes).await?;
+    tx.commit().await?;
+    hold.keep();
+
+    user_id_to_livestreams_cache.invalidate(&user_id).await;
+    tags_cache.invalidate(&livestream_id).await;
+    collaborators_cache.invalidate(&livestream_id).await;
+
+    // コミット前のタグやコラボレーターをキャッシュに入れないよう、コミットした後に読み込む
     let livestream = fill_livestream_response(
-        &mut tx,
+        &mut *pool.acquire().await?,
         LivestreamModel {
             id: livestream_id,
             user_id,
@@ -689,79 +1475,416 @@ async fn reserve_livestream_handler(
         },
         &user_cache,
         &tags_cache,
+        &collaborators_cache,
+    )
+    .await?;
+
+    Ok((StatusCode::CREATED, axum::Json(livestream)))
+}
+
+/// ライブ配信のコラボレーター全員に通知する
+async fn notify_collaborators(
+    tx: &mut MySqlConnection,
+    livestream_id: i64,
+    kind: NotificationKind,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO notifications (user_id, kind, livestream_id, created_at) SELECT user_id, ?, livestream_id, ? FROM livestream_collaborators WHERE livestream_id = ?",
+    )
+    .bind(kind.as_str())
+    .bind(Utc::now().timestamp())
+    .bind(livestream_id)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+/// 日時を変更、キャンセルするライブ配信をロックして取り直す。配信開始後のものは変更できない
+async fn lock_upcoming_livestream(
+    tx: &mut MySqlConnection,
+    livestream_id: i64,
+) -> Result<LivestreamModel, Error> {
+    let livestream_model: LivestreamModel =
+        sqlx::query_as("SELECT * FROM livestreams WHERE id = ? FOR UPDATE")
+            .bind(livestream_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(Error::NotFound("livestream not found".into()))?;
+    if livestream_model.start_at <= Utc::now().timestamp() {
+        return Err(Error::BadRequest(
+            "配信開始後のライブ配信は変更できません".into(),
+        ));
+    }
+    Ok(livestream_model)
+}
+
+// 配信開始前のライブ配信をキャンセルし、予約枠を返す
+// DELETE /api/livestream/:livestream_id
+async fn cancel_livestream_handler(
+    State(AppState {
+        pool,
+        tags_cache,
+        collaborators_cache,
+        livestream_cache,
+        user_id_to_livestreams_cache,
+        ng_word_matcher_cache,
+        slot_ledger,
+        ..
+    }): State<AppState>,
+    OwnedLivestreamPath {
+        livestream_id,
+        user_id,
+        ..
+    }: OwnedLivestreamPath,
+) -> Result<StatusCode, Error> {
+    let mut tx = pool.begin().await?;
+
+    // 同じライブ配信への並列なキャンセルで予約枠を二重に返さないよう、先にライブ配信をロックする
+    let livestream_model = lock_upcoming_livestream(&mut tx, livestream_id).await?;
+    let slot_changes = [(livestream_model.start_at, livestream_model.end_at, 1)];
+    let hold = slot_ledger.hold(&slot_changes).map_err(|_| {
+        reservation_unavailable(
+            slot_ledger.config(),
+            livestream_model.start_at,
+            livestream_model.end_at,
+        )
+    })?;
+
+    // トリガーで加算した配信者のスコアから、このライブ配信の分を差し引く
+    sqlx::query(
+        r#"
+        UPDATE user_score
+        SET
+            total_reactions = total_reactions - (SELECT COUNT(*) FROM reactions WHERE livestream_id = ?),
+            total_tip = total_tip - (SELECT IFNULL(SUM(tip), 0) FROM livecomments WHERE livestream_id = ?),
+            total_livecomments = total_livecomments - (SELECT COUNT(*) FROM livecomments WHERE livestream_id = ?)
+        WHERE user_id = ?
+        "#,
     )
+    .bind(livestream_id)
+    .bind(livestream_id)
+    .bind(livestream_id)
+    .bind(user_id)
+    .execute(&mut *tx)
     .await?;
 
+    // 通知はライブ配信を削除した後も残す
+    notify_collaborators(&mut tx, livestream_id, NotificationKind::LivestreamCanceled).await?;
+
+    for query in [
+        "DELETE FROM livestream_tags WHERE livestream_id = ?",
+        "DELETE FROM livecomment_reports WHERE livestream_id = ?",
+        "DELETE FROM livecomments WHERE livestream_id = ?",
+        "DELETE FROM reactions WHERE livestream_id = ?",
+        "DELETE FROM ng_words WHERE livestream_id = ?",
+        "DELETE FROM livestream_viewers_history WHERE livestream_id = ?",
+        "DELETE FROM livestream_score WHERE livestream_id = ?",
+        "DELETE FROM livestream_collaborators WHERE livestream_id = ?",
+        "DELETE FROM livestreams WHERE id = ?",
+    ] {
+        sqlx::query(query)
+            .bind(livestream_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    write_reservation_slots(&mut tx, &slot_changes).await?;
     tx.commit().await?;
+    hold.keep();
 
-    Ok((StatusCode::CREATED, axum::Json(livestream)))
+    livestream_cache.invalidate(&livestream_id).await;
+    tags_cache.invalidate(&livestream_id).await;
+    collaborators_cache.invalidate(&livestream_id).await;
+    ng_word_matcher_cache.invalidate(&livestream_id).await;
+    user_id_to_livestreams_cache.invalidate(&user_id).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RescheduleLivestreamRequest {
+    start_at: i64,
+    end_at: i64,
+}
+
+// 配信開始前のライブ配信の日時を変更する。元の予約枠を返してから新しい予約枠を確保する
+// PATCH /api/livestream/:livestream_id
+async fn reschedule_livestream_handler(
+    State(AppState {
+        pool,
+        user_cache,
+        tags_cache,
+        collaborators_cache,
+        livestream_cache,
+        user_id_to_livestreams_cache,
+        slot_ledger,
+        ..
+    }): State<AppState>,
+    OwnedLivestreamPath {
+        livestream_id,
+        user_id,
+        ..
+    }: OwnedLivestreamPath,
+    axum::Json(req): axum::Json<RescheduleLivestreamRequest>,
+) -> Result<axum::Json<Livestream>, Error> {
+    slot_ledger.config().check(req.start_at, req.end_at)?;
+    if req.start_at <= Utc::now().timestamp() {
+        return Err(Error::BadRequest("start_at must be in the future".into()));
+    }
+
+    let mut tx = pool.begin().await?;
+
+    let mut livestream_model = lock_upcoming_livestream(&mut tx, livestream_id).await?;
+    // 元の区間と新しい区間が重なっていても、返した枠を取り直せるようまとめて適用する
+    let slot_changes = [
+        (livestream_model.start_at, livestream_model.end_at, 1),
+        (req.start_at, req.end_at, -1),
+    ];
+    let hold = slot_ledger
+        .hold(&slot_changes)
+        .map_err(|_| reservation_unavailable(slot_ledger.config(), req.start_at, req.end_at))?;
+
+    sqlx::query("UPDATE livestreams SET start_at = ?, end_at = ? WHERE id = ?")
+        .bind(req.start_at)
+        .bind(req.end_at)
+        .bind(livestream_id)
+        .execute(&mut *tx)
+        .await?;
+    livestream_model.start_at = req.start_at;
+    livestream_model.end_at = req.end_at;
+    notify_collaborators(
+        &mut tx,
+        livestream_id,
+        NotificationKind::LivestreamRescheduled,
+    )
+    .await?;
+
+    let livestream = fill_livestream_response(
+        &mut tx,
+        livestream_model,
+        &user_cache,
+        &tags_cache,
+        &collaborators_cache,
+    )
+    .await?;
+
+    write_reservation_slots(&mut tx, &slot_changes).await?;
+    tx.commit().await?;
+    hold.keep();
+
+    livestream_cache.invalidate(&livestream_id).await;
+    user_id_to_livestreams_cache.invalidate(&user_id).await;
+
+    Ok(axum::Json(livestream))
 }
 
 #[derive(Debug, serde::Deserialize)]
 struct SearchLivestreamsQuery {
     #[serde(default)]
     tag: String,
+    /// カンマ区切りのタグ名。tag と合わせて tag_mode で絞り込む
+    #[serde(default)]
+    tags: String,
     #[serde(default)]
-    limit: String,
+    tag_mode: TagMode,
+    status: Option<LivestreamStatus>,
+    /// 配信期間が [from, to) と重なるものに絞り込む
+    from: Option<i64>,
+    to: Option<i64>,
+    /// 配信者のユーザー名
+    owner: Option<String>,
+    #[serde(default)]
+    sort: LivestreamSort,
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TagMode {
+    /// いずれかのタグを持つ
+    #[default]
+    Or,
+    /// 全てのタグを持つ
+    And,
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum LivestreamSort {
+    /// 新しく予約されたものから
+    #[default]
+    Newest,
+    /// 配信開始が早いものから
+    StartingSoon,
+    /// リアクション数とチップの合計が多いものから
+    Popular,
+}
+
+impl LivestreamSort {
+    /// ページングのカーソルに使う並び順のキー。大きいものから並べる。
+    /// Newest は id だけで並べるので 0 にしておく
+    fn sort_key_sql(self) -> &'static str {
+        match self {
+            Self::Newest => "0",
+            Self::StartingSoon => "-l.start_at",
+            // リアクションとチップのたびにトリガーで更新している
+            Self::Popular => "ls.score",
+        }
+    }
+
+    /// push_sql に渡す並び順のキーの列。None なら id だけで並べる
+    fn sort_key_column(self) -> Option<&'static str> {
+        match self {
+            Self::Newest => None,
+            Self::StartingSoon | Self::Popular => Some("l.sort_key"),
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct LivestreamSearchRow {
+    #[sqlx(flatten)]
+    livestream: LivestreamModel,
+    sort_key: i64,
+}
+
+/// ライブ配信は id の降順に並べる
+fn livestream_cursor(livestream_model: &LivestreamModel) -> pagination::Cursor {
+    pagination::Cursor {
+        created_at: 0,
+        id: livestream_model.id,
+    }
 }
 
 async fn search_livestreams_handler(
     State(AppState {
-        pool, user_cache, ..
+        pool,
+        user_cache,
+        tags_cache,
+        collaborators_cache,
+        ..
     }): State<AppState>,
-    Query(SearchLivestreamsQuery {
-        tag: key_tag_name,
-        limit,
-    }): Query<SearchLivestreamsQuery>,
-) -> Result<axum::Json<Vec<Livestream>>, Error> {
+    axum::extract::OriginalUri(uri): axum::extract::OriginalUri,
+    Query(query): Query<SearchLivestreamsQuery>,
+    Query(page): Query<pagination::PageQuery>,
+) -> Result<(axum::http::HeaderMap, axum::Json<Vec<Livestream>>), Error> {
+    let page = page.parse()?;
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            return Err(Error::BadRequest("from must not be after to".into()));
+        }
+    }
+    let mut tag_names: Vec<&str> = std::iter::once(query.tag.as_str())
+        .chain(query.tags.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect();
+    tag_names.sort_unstable();
+    tag_names.dedup();
+
     let mut tx = pool.begin().await?;
 
-    let livestream_models: Vec<LivestreamModel> = if key_tag_name.is_empty() {
-        // 検索条件なし
-        let mut query = "SELECT * FROM livestreams ORDER BY id DESC".to_owned();
-        if !limit.is_empty() {
-            let limit: i64 = limit
-                .parse()
-                .map_err(|_| Error::BadRequest("failed to parse limit".into()))?;
-            query = format!("{} LIMIT {}", query, limit);
+    // 並び順のキーでカーソルの条件を書けるようにサブクエリにする
+    let mut query_builder = QueryBuilder::new("SELECT * FROM (SELECT l.*, ");
+    query_builder.push(query.sort.sort_key_sql());
+    query_builder.push(" AS sort_key FROM livestreams l");
+    if let LivestreamSort::Popular = query.sort {
+        query_builder.push(" INNER JOIN livestream_score ls ON ls.livestream_id = l.id");
+    }
+    query_builder.push(" WHERE TRUE");
+    if !tag_names.is_empty() {
+        // タグによる絞り込み
+        query_builder.push(
+            r#"
+            AND l.id IN (
+                SELECT lt.livestream_id
+                FROM livestream_tags lt
+                INNER JOIN tags t ON lt.tag_id = t.id
+                WHERE t.name IN ("#,
+        );
+        let mut separated = query_builder.separated(", ");
+        for tag_name in &tag_names {
+            separated.push_bind(*tag_name);
         }
-        sqlx::query_as(&query).fetch_all(&mut *tx).await?
-    } else {
-        // タグによる取得
-        let query = r#"
-            SELECT *
-            FROM livestreams l
-            WHERE l.id IN (
-                    SELECT livestream_id
-                    FROM livestream_tags lt
-                    LEFT JOIN tags t ON lt.tag_id=t.id
-                    WHERE t.name=?
-                )
-            ORDER BY l.id DESC
-            "#;
-        sqlx::query_as(query)
-            .bind(key_tag_name)
-            .fetch_all(&mut *tx)
-            .await?
-    };
-
-    let livestreams = fill_livestream_responses(&mut tx, livestream_models, &user_cache).await?;
+        separated.push_unseparated(")");
+        if let TagMode::And = query.tag_mode {
+            query_builder.push(" GROUP BY lt.livestream_id HAVING COUNT(DISTINCT t.id) = ");
+            query_builder.push_bind(tag_names.len() as i64);
+        }
+        query_builder.push(")");
+    }
+    if let Some(status) = query.status {
+        let now = Utc::now().timestamp();
+        match status {
+            LivestreamStatus::Upcoming => {
+                query_builder.push(" AND l.start_at > ");
+                query_builder.push_bind(now);
+            }
+            LivestreamStatus::Live => {
+                query_builder.push(" AND l.start_at <= ");
+                query_builder.push_bind(now);
+                query_builder.push(" AND l.end_at > ");
+                query_builder.push_bind(now);
+            }
+            LivestreamStatus::Ended => {
+                query_builder.push(" AND l.end_at <= ");
+                query_builder.push_bind(now);
+            }
+        }
+    }
+    if let Some(from) = query.from {
+        query_builder.push(" AND l.end_at > ");
+        query_builder.push_bind(from);
+    }
+    if let Some(to) = query.to {
+        query_builder.push(" AND l.start_at < ");
+        query_builder.push_bind(to);
+    }
+    if let Some(owner) = query.owner {
+        query_builder.push(" AND l.user_id IN (SELECT id FROM users WHERE name = ");
+        query_builder.push_bind(owner);
+        query_builder.push(")");
+    }
+    query_builder.push(") l WHERE TRUE");
+    page.push_sql(&mut query_builder, query.sort.sort_key_column(), "l.id");
+    let mut rows: Vec<LivestreamSearchRow> =
+        query_builder.build_query_as().fetch_all(&mut *tx).await?;
+    let next = page.finish(&mut rows, |row| pagination::Cursor {
+        created_at: row.sort_key,
+        id: row.livestream.id,
+    });
+    let livestream_models = rows.into_iter().map(|row| row.livestream).collect();
+    let livestreams = fill_livestream_responses(
+        &mut tx,
+        livestream_models,
+        &user_cache,
+        &tags_cache,
+        &collaborators_cache,
+    )
+    .await?;
 
     tx.commit().await?;
 
-    Ok(axum::Json(livestreams))
+    Ok((
+        pagination::link_headers(&uri, next),
+        axum::Json(livestreams),
+    ))
 }
 
 async fn get_my_livestreams_handler(
     State(AppState {
         pool,
         user_cache,
+        tags_cache,
+        collaborators_cache,
         user_id_to_livestreams_cache,
         ..
     }): State<AppState>,
     jar: SignedCookieJar,
-) -> Result<axum::Json<Vec<Livestream>>, Error> {
+    axum::extract::OriginalUri(uri): axum::extract::OriginalUri,
+    Query(page): Query<pagination::PageQuery>,
+) -> Result<(axum::http::HeaderMap, axum::Json<Vec<Livestream>>), Error> {
     verify_user_session(&jar).await?;
+    let page = page.parse()?;
 
     let cookie = jar.get(DEFAULT_SESSION_ID_KEY).ok_or(Error::SessionError)?;
     let sess = CookieStore::new()
@@ -773,25 +1896,41 @@ async fn get_my_livestreams_handler(
     let mut tx = pool.begin().await?;
     let livestream_models = user_id_to_livestreams_cache
         .get_or_insert(&mut tx, user_id)
-        .await;
-    let livestreams = fill_livestream_responses(&mut tx, livestream_models, &user_cache).await?;
+        .await?;
+    let (livestream_models, next) = page.apply(livestream_models, livestream_cursor);
+    let livestreams = fill_livestream_responses(
+        &mut tx,
+        livestream_models,
+        &user_cache,
+        &tags_cache,
+        &collaborators_cache,
+    )
+    .await?;
 
     tx.commit().await?;
 
-    Ok(axum::Json(livestreams))
+    Ok((
+        pagination::link_headers(&uri, next),
+        axum::Json(livestreams),
+    ))
 }
 
 async fn get_user_livestreams_handler(
     State(AppState {
         pool,
         user_cache,
+        tags_cache,
+        collaborators_cache,
         user_id_to_livestreams_cache,
         ..
     }): State<AppState>,
     jar: SignedCookieJar,
     Path((username,)): Path<(String,)>,
-) -> Result<axum::Json<Vec<Livestream>>, Error> {
+    axum::extract::OriginalUri(uri): axum::extract::OriginalUri,
+    Query(page): Query<pagination::PageQuery>,
+) -> Result<(axum::http::HeaderMap, axum::Json<Vec<Livestream>>), Error> {
     verify_user_session(&jar).await?;
+    let page = page.parse()?;
 
     let mut tx = pool.begin().await?;
 
@@ -803,29 +1942,34 @@ async fn get_user_livestreams_handler(
 
     let livestream_models: Vec<LivestreamModel> = user_id_to_livestreams_cache
         .get_or_insert(&mut tx, user.id)
-        .await;
-    let livestreams = fill_livestream_responses(&mut tx, livestream_models, &user_cache).await?;
+        .await?;
+    let (livestream_models, next) = page.apply(livestream_models, livestream_cursor);
+    let livestreams = fill_livestream_responses(
+        &mut tx,
+        livestream_models,
+        &user_cache,
+        &tags_cache,
+        &collaborators_cache,
+    )
+    .await?;
 
     tx.commit().await?;
 
-    Ok(axum::Json(livestreams))
+    Ok((
+        pagination::link_headers(&uri, next),
+        axum::Json(livestreams),
+    ))
 }
 
 // viewerテーブルの廃止
 async fn enter_livestream_handler(
     State(AppState { pool, .. }): State<AppState>,
-    jar: SignedCookieJar,
-    Path((livestream_id,)): Path<(i64,)>,
+    LivestreamPath {
+        livestream_id,
+        user_id,
+        ..
+    }: LivestreamPath,
 ) -> Result<(), Error> {
-    verify_user_session(&jar).await?;
-
-    let cookie = jar.get(DEFAULT_SESSION_ID_KEY).ok_or(Error::SessionError)?;
-    let sess = CookieStore::new()
-        .load_session(cookie.value().to_owned())
-        .await?
-        .ok_or(Error::SessionError)?;
-    let user_id: i64 = sess.get(DEFAULT_USER_ID_KEY).ok_or(Error::SessionError)?;
-
     let mut tx = pool.begin().await?;
 
     let created_at = Utc::now().timestamp();
@@ -845,19 +1989,13 @@ async fn enter_livestream_handler(
 
 async fn exit_livestream_handler(
     State(AppState { pool, .. }): State<AppState>,
-    jar: SignedCookieJar,
-    Path((livestream_id,)): Path<(i64,)>,
+    LivestreamPath {
+        livestream_id,
+        user_id,
+        ..
+    }: LivestreamPath,
 ) -> Result<(), Error> {
-    verify_user_session(&jar).await?;
-
-    let cookie = jar.get(DEFAULT_SESSION_ID_KEY).ok_or(Error::SessionError)?;
-    let sess = CookieStore::new()
-        .load_session(cookie.value().to_owned())
-        .await?
-        .ok_or(Error::SessionError)?;
-    let user_id: i64 = sess.get(DEFAULT_USER_ID_KEY).ok_or(Error::SessionError)?;
-
-    let mut tx = pool.begin().await?;
+    let mut tx = pool.begin().await?;
 
     sqlx::query("DELETE FROM livestream_viewers_history WHERE user_id = ? AND livestream_id = ?")
         .bind(user_id)
@@ -875,86 +2013,81 @@ async fn get_livestream_handler(
         pool,
         user_cache,
         tags_cache,
-        livestream_cache,
+        collaborators_cache,
         ..
     }): State<AppState>,
-    jar: SignedCookieJar,
-    Path((livestream_id,)): Path<(i64,)>,
+    LivestreamPath { livestream, .. }: LivestreamPath,
 ) -> Result<axum::Json<Livestream>, Error> {
-    verify_user_session(&jar).await?;
-
     let mut tx = pool.begin().await?;
 
-    let livestream_model: LivestreamModel = livestream_cache
-        .get_or_insert(&mut tx, livestream_id)
-        .await
-        .ok_or(Error::NotFound(
-            "not found livestream that has the given id".into(),
-        ))?;
-
-    let livestream =
-        fill_livestream_response(&mut tx, livestream_model, &user_cache, &tags_cache).await?;
+    let livestream = fill_livestream_response(
+        &mut tx,
+        livestream,
+        &user_cache,
+        &tags_cache,
+        &collaborators_cache,
+    )
+    .await?;
 
     tx.commit().await?;
 
     Ok(axum::Json(livestream))
 }
 
+#[derive(Debug, serde::Deserialize)]
+struct GetLivecommentReportsQuery {
+    /// 指定されなければ全ての状態の報告を返す
+    status: Option<ReportStatus>,
+}
+
 async fn get_livecomment_reports_handler(
     State(AppState {
         pool,
         user_cache,
         tags_cache,
+        collaborators_cache,
         livestream_cache,
         ..
     }): State<AppState>,
-    jar: SignedCookieJar,
-    Path((livestream_id,)): Path<(i64,)>,
-) -> Result<axum::Json<Vec<LivecommentReport>>, Error> {
-    verify_user_session(&jar).await?;
-
-    let cookie = jar.get(DEFAULT_SESSION_ID_KEY).ok_or(Error::SessionError)?;
-    let sess = CookieStore::new()
-        .load_session(cookie.value().to_owned())
-        .await?
-        .ok_or(Error::SessionError)?;
-    let user_id: i64 = sess.get(DEFAULT_USER_ID_KEY).ok_or(Error::SessionError)?;
+    ModeratedLivestreamPath { livestream_id, .. }: ModeratedLivestreamPath,
+    Query(GetLivecommentReportsQuery { status }): Query<GetLivecommentReportsQuery>,
+    Query(expand): Query<expand::ExpandQuery>,
+) -> Result<axum::response::Response, Error> {
+    let expand = expand.parse(
+        LIVECOMMENT_REPORT_FIELDS,
+        &["reporter", "livecomment", "user", "livestream"],
+    )?;
 
     let mut tx = pool.begin().await?;
 
-    let livestream_model: LivestreamModel = livestream_cache
-        .get_or_insert(&mut tx, livestream_id)
-        .await
-        .ok_or(Error::Sqlx(sqlx::Error::RowNotFound))?;
-
-    if livestream_model.user_id != user_id {
-        return Err(Error::Forbidden(
-            "can't get other streamer's livecomment reports".into(),
-        ));
-    }
-
-    let report_models: Vec<LivecommentReportModel> =
-        sqlx::query_as("SELECT * FROM livecomment_reports WHERE livestream_id = ?")
-            .bind(livestream_id)
-            .fetch_all(&mut *tx)
-            .await?;
+    let report_models: Vec<LivecommentReportModel> = sqlx::query_as(
+        "SELECT * FROM livecomment_reports WHERE livestream_id = ? AND (? IS NULL OR status = ?)",
+    )
+    .bind(livestream_id)
+    .bind(status.map(ReportStatus::as_str))
+    .bind(status.map(ReportStatus::as_str))
+    .fetch_all(&mut *tx)
+    .await?;
 
-    let mut reports = Vec::with_capacity(report_models.len());
-    for report_model in report_models {
-        let report = fill_livecomment_report_response(
-            &mut tx,
-            report_model,
-            &user_cache,
-            &tags_cache,
-            &livestream_cache,
-        )
-        .await?;
-        reports.push(report);
-    }
+    let mut loader = ResponseLoader::new(
+        &user_cache,
+        &tags_cache,
+        &collaborators_cache,
+        &livestream_cache,
+    );
+    let mut included = Included::default();
+    let reports = fill_livecomment_report_responses(
+        &mut tx,
+        report_models,
+        &mut loader,
+        &expand,
+        &mut included,
+    )
+    .await?;
 
     tx.commit().await?;
 
-    Ok(axum::Json(reports))
+    expand.respond(reports, included)
 }
 
 #[derive(FromRow)]
@@ -966,9 +2099,9 @@ struct TagModelWithLivestreamId {
 
 async fn fill_tags_for_livestreams(
     tx: &mut MySqlConnection,
-    livestream_models: &Vec<LivestreamModel>,
+    livestream_ids: &[i64],
 ) -> sqlx::Result<HashMap<i64, Vec<Tag>>> {
-    let models: Vec<TagModelWithLivestreamId> = if livestream_models.is_empty() {
+    let models: Vec<TagModelWithLivestreamId> = if livestream_ids.is_empty() {
         Vec::new()
     } else {
         let mut query_builder = QueryBuilder::new(
@@ -980,8 +2113,8 @@ async fn fill_tags_for_livestreams(
         );
 
         let mut separated = query_builder.separated(", ");
-        for livestream_model in livestream_models {
-            separated.push_bind(livestream_model.id);
+        for livestream_id in livestream_ids {
+            separated.push_bind(livestream_id);
         }
         separated.push_unseparated(") ");
 
@@ -1009,18 +2142,43 @@ async fn fill_livestream_responses(
     tx: &mut MySqlConnection,
     livestream_models: Vec<LivestreamModel>,
     user_cache: &UserCache,
-) -> sqlx::Result<Vec<Livestream>> {
-    let tag_map = fill_tags_for_livestreams(tx, &livestream_models).await?;
+    tags_cache: &TagsCache,
+    collaborators_cache: &CollaboratorsCache,
+) -> Result<Vec<Livestream>, Error> {
+    let livestream_ids: Vec<i64> = livestream_models.iter().map(|m| m.id).collect();
+    let tag_map = tags_cache
+        .get_or_insert_many(&mut *tx, &livestream_ids)
+        .await?;
+    let collaborator_map = collaborators_cache
+        .get_or_insert_many(&mut *tx, &livestream_ids)
+        .await?;
+    // 配信者とコラボレーターをまとめて読み込む
+    let user_ids: Vec<i64> = livestream_models
+        .iter()
+        .map(|m| m.user_id)
+        .chain(collaborator_map.values().flatten().copied())
+        .collect();
+    let users = user_cache.get_or_insert_many(&mut *tx, &user_ids).await?;
+    let get_user = |user_id: &i64| {
+        users
+            .get(user_id)
+            .cloned()
+            .flatten()
+            .ok_or(sqlx::Error::RowNotFound)
+    };
 
     let mut res = Vec::with_capacity(livestream_models.len());
 
     for model in livestream_models.into_iter() {
-        let owner = user_cache
-            .get_or_insert(tx, model.user_id)
-            .await
-            .ok_or(sqlx::Error::RowNotFound)?;
-        let tags: Vec<Tag> = tag_map.get(&model.id).unwrap_or(&Vec::new()).to_vec();
-        res.push(Livestream::from((model, tags, owner)));
+        let owner = get_user(&model.user_id)?;
+        let tags: Vec<Tag> = tag_map.get(&model.id).cloned().unwrap_or_default();
+        let collaborators = collaborator_map
+            .get(&model.id)
+            .into_iter()
+            .flatten()
+            .map(get_user)
+            .collect::<Result<Vec<_>, _>>()?;
+        res.push(Livestream::from((model, tags, owner, collaborators)));
     }
     Ok(res)
 }
@@ -1030,14 +2188,170 @@ async fn fill_livestream_response(
     livestream_model: LivestreamModel,
     user_cache: &UserCache,
     tags_cache: &TagsCache,
-) -> sqlx::Result<Livestream> {
-    let owner = user_cache
-        .get_or_insert(tx, livestream_model.user_id)
-        .await
-        .ok_or(sqlx::Error::RowNotFound)?;
-    let tags = tags_cache.get_or_insert(tx, livestream_model.id).await;
+    collaborators_cache: &CollaboratorsCache,
+) -> Result<Livestream, Error> {
+    let livestreams = fill_livestream_responses(
+        tx,
+        vec![livestream_model],
+        user_cache,
+        tags_cache,
+        collaborators_cache,
+    )
+    .await?;
+    Ok(livestreams.into_iter().next().unwrap())
+}
+
+/// 省略形式のレスポンスで id のみを返した関連の実体
+#[derive(Debug, Default, serde::Serialize)]
+struct Included {
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    users: BTreeMap<i64, User>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    livestreams: BTreeMap<i64, Livestream>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    livecomments: BTreeMap<i64, Livecomment>,
+}
+
+/// 一覧のレスポンスで参照するユーザーとライブ配信を、それぞれ一度だけ読み込む
+struct ResponseLoader<'a> {
+    user_cache: &'a UserCache,
+    tags_cache: &'a TagsCache,
+    collaborators_cache: &'a CollaboratorsCache,
+    livestream_cache: &'a LivestreamCache,
+    users: HashMap<i64, User>,
+    livestreams: HashMap<i64, Livestream>,
+}
+
+impl<'a> ResponseLoader<'a> {
+    fn new(
+        user_cache: &'a UserCache,
+        tags_cache: &'a TagsCache,
+        collaborators_cache: &'a CollaboratorsCache,
+        livestream_cache: &'a LivestreamCache,
+    ) -> Self {
+        Self {
+            user_cache,
+            tags_cache,
+            collaborators_cache,
+            livestream_cache,
+            users: HashMap::new(),
+            livestreams: HashMap::new(),
+        }
+    }
+
+    async fn load_users(
+        &mut self,
+        tx: &mut MySqlConnection,
+        user_ids: impl IntoIterator<Item = i64>,
+    ) -> Result<(), Error> {
+        let user_ids: Vec<i64> = user_ids
+            .into_iter()
+            .filter(|user_id| !self.users.contains_key(user_id))
+            .collect();
+        let users = self.user_cache.get_or_insert_many(tx, &user_ids).await?;
+        for (user_id, user) in users {
+            self.users
+                .insert(user_id, user.ok_or(sqlx::Error::RowNotFound)?);
+        }
+        Ok(())
+    }
+
+    /// 削除されたライブ配信は読み込まない
+    async fn load_livestreams(
+        &mut self,
+        tx: &mut MySqlConnection,
+        livestream_ids: impl IntoIterator<Item = i64>,
+    ) -> Result<(), Error> {
+        let livestream_ids: Vec<i64> = livestream_ids
+            .into_iter()
+            .filter(|livestream_id| !self.livestreams.contains_key(livestream_id))
+            .collect();
+        let livestream_models = self
+            .livestream_cache
+            .get_or_insert_many(&mut *tx, &livestream_ids)
+            .await?
+            .into_values()
+            .flatten()
+            .collect();
+        let livestreams = fill_livestream_responses(
+            tx,
+            livestream_models,
+            self.user_cache,
+            self.tags_cache,
+            self.collaborators_cache,
+        )
+        .await?;
+        for livestream in livestreams {
+            self.livestreams.insert(livestream.id, livestream);
+        }
+        Ok(())
+    }
+
+    /// load_users で読み込んだユーザーを relation に応じて返す
+    fn user(
+        &self,
+        relation: expand::Relation,
+        user_id: i64,
+        included: &mut Included,
+    ) -> Result<expand::Expandable<User>, Error> {
+        let get = || {
+            self.users
+                .get(&user_id)
+                .cloned()
+                .ok_or(sqlx::Error::RowNotFound)
+        };
+        Ok(match relation {
+            expand::Relation::Embed => expand::Expandable::Full(get()?),
+            expand::Relation::Id => {
+                if let std::collections::btree_map::Entry::Vacant(entry) =
+                    included.users.entry(user_id)
+                {
+                    entry.insert(get()?);
+                }
+                expand::Expandable::Id(user_id)
+            }
+            expand::Relation::Omit => expand::Expandable::Id(user_id),
+        })
+    }
+
+    /// load_livestreams で読み込んだライブ配信を relation に応じて返す
+    fn livestream(
+        &self,
+        relation: expand::Relation,
+        livestream_id: i64,
+        included: &mut Included,
+    ) -> Result<expand::Expandable<Livestream>, Error> {
+        let get = || {
+            self.livestreams
+                .get(&livestream_id)
+                .cloned()
+                .ok_or(sqlx::Error::RowNotFound)
+        };
+        Ok(match relation {
+            expand::Relation::Embed => expand::Expandable::Full(get()?),
+            expand::Relation::Id => {
+                if let std::collections::btree_map::Entry::Vacant(entry) =
+                    included.livestreams.entry(livestream_id)
+                {
+                    entry.insert(get()?);
+                }
+                expand::Expandable::Id(livestream_id)
+            }
+            expand::Relation::Omit => expand::Expandable::Id(livestream_id),
+        })
+    }
+}
 
-    Ok(Livestream::from((livestream_model, tags, owner)))
+/// Omit 以外の関連の id
+fn related_ids<T>(
+    relation: expand::Relation,
+    items: &[T],
+    id: impl Fn(&T) -> i64,
+) -> std::collections::HashSet<i64> {
+    if relation == expand::Relation::Omit {
+        return std::collections::HashSet::new();
+    }
+    items.iter().map(id).collect()
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -1056,24 +2370,30 @@ struct LivecommentModel {
     created_at: i64,
 }
 
-#[derive(Debug, serde::Serialize)]
+#[derive(Debug, serde::Serialize, Clone)]
 struct Livecomment {
     id: i64,
-    user: User,
-    livestream: Livestream,
+    user: expand::Expandable<User>,
+    livestream: expand::Expandable<Livestream>,
     comment: String,
     tip: i64,
     created_at: i64,
 }
 
+const LIVECOMMENT_FIELDS: &[&str] = &["id", "user", "livestream", "comment", "tip", "created_at"];
+
 #[derive(Debug, serde::Serialize)]
 struct LivecommentReport {
     id: i64,
-    reporter: User,
-    livecomment: Livecomment,
+    reporter: expand::Expandable<User>,
+    livecomment: expand::Expandable<Livecomment>,
+    status: ReportStatus,
     created_at: i64,
 }
 
+const LIVECOMMENT_REPORT_FIELDS: &[&str] =
+    &["id", "reporter", "livecomment", "status", "created_at"];
+
 #[derive(Debug, sqlx::FromRow)]
 struct LivecommentReportModel {
     id: i64,
@@ -1081,12 +2401,50 @@ struct LivecommentReportModel {
     #[allow(unused)]
     livestream_id: i64,
     livecomment_id: i64,
+    #[sqlx(try_from = "String")]
+    status: ReportStatus,
     created_at: i64,
 }
 
+/// 配信者による報告の対応状況。livecomment_reports.status に保存する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ReportStatus {
+    Open,
+    /// 問題なしとして却下した
+    Dismissed,
+    /// 対応済み (ライブコメントを非表示にした)
+    Actioned,
+}
+
+impl ReportStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Dismissed => "dismissed",
+            Self::Actioned => "actioned",
+        }
+    }
+}
+
+impl TryFrom<String> for ReportStatus {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "open" => Ok(Self::Open),
+            "dismissed" => Ok(Self::Dismissed),
+            "actioned" => Ok(Self::Actioned),
+            _ => Err(format!("unknown report status: {s}")),
+        }
+    }
+}
+
 #[derive(Debug, serde::Deserialize)]
 struct ModerateRequest {
     ng_word: String,
+    #[serde(default)]
+    mode: ngword::Mode,
 }
 
 #[derive(Debug, serde::Serialize, sqlx::FromRow)]
@@ -1095,82 +2453,457 @@ struct NgWord {
     user_id: i64,
     livestream_id: i64,
     word: String,
+    #[sqlx(try_from = "String")]
+    mode: ngword::Mode,
     #[sqlx(default)]
     created_at: i64,
 }
 
-#[derive(Debug, serde::Deserialize)]
-struct GetLivecommentsQuery {
-    #[serde(default)]
-    limit: String,
-}
-
 async fn get_livecomments_handler(
     State(AppState {
         pool,
         user_cache,
         tags_cache,
+        collaborators_cache,
         livestream_cache,
         ..
     }): State<AppState>,
-    jar: SignedCookieJar,
-    Path((livestream_id,)): Path<(i64,)>,
-    Query(GetLivecommentsQuery { limit }): Query<GetLivecommentsQuery>,
-) -> Result<axum::Json<Vec<Livecomment>>, Error> {
-    verify_user_session(&jar).await?;
+    LivestreamPath { livestream_id, .. }: LivestreamPath,
+    axum::extract::OriginalUri(uri): axum::extract::OriginalUri,
+    Query(page): Query<pagination::PageQuery>,
+    Query(expand): Query<expand::ExpandQuery>,
+) -> Result<(axum::http::HeaderMap, axum::response::Response), Error> {
+    let page = page.parse()?;
+    let expand = expand.parse(LIVECOMMENT_FIELDS, &["user", "livestream"])?;
 
     let mut tx = pool.begin().await?;
 
-    let mut query =
-        "SELECT * FROM livecomments WHERE livestream_id = ? ORDER BY created_at DESC".to_owned();
-    if !limit.is_empty() {
-        let limit: i64 = limit.parse().map_err(|_| Error::BadRequest("".into()))?;
-        query = format!("{} LIMIT {}", query, limit);
+    let mut query_builder = QueryBuilder::new(
+        "SELECT * FROM livecomments WHERE deleted_at IS NULL AND livestream_id = ",
+    );
+    query_builder.push_bind(livestream_id);
+    page.push_sql(&mut query_builder, Some("created_at"), "id");
+    let mut livecomment_models: Vec<LivecommentModel> =
+        query_builder.build_query_as().fetch_all(&mut *tx).await?;
+    let next = page.finish(&mut livecomment_models, |m| pagination::Cursor {
+        created_at: m.created_at,
+        id: m.id,
+    });
+
+    let mut loader = ResponseLoader::new(
+        &user_cache,
+        &tags_cache,
+        &collaborators_cache,
+        &livestream_cache,
+    );
+    let mut included = Included::default();
+    let livecomments = fill_livecomment_responses(
+        &mut tx,
+        livecomment_models,
+        &mut loader,
+        expand.relation("user"),
+        expand.relation("livestream"),
+        &mut included,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok((
+        pagination::link_headers(&uri, next),
+        expand.respond(livecomments, included)?,
+    ))
+}
+
+/// 一度に DB から読み込むライブコメント数の上限
+const LIVECOMMENT_STREAM_BACKLOG_LIMIT: i64 = 1000;
+
+/// livecomments から after_id より大きい id のライブコメントを id の昇順に読み込む
+async fn load_livecomment_events(
+    state: &AppState,
+    livestream_id: i64,
+    after_id: i64,
+) -> Result<Vec<std::sync::Arc<livestream_hub::LivestreamEvent>>, Error> {
+    let mut tx = state.pool.begin().await?;
+
+    let livecomment_models: Vec<LivecommentModel> = sqlx::query_as(
+        "SELECT * FROM livecomments WHERE livestream_id = ? AND id > ? AND deleted_at IS NULL ORDER BY id ASC LIMIT ?",
+    )
+    .bind(livestream_id)
+    .bind(after_id)
+    .bind(LIVECOMMENT_STREAM_BACKLOG_LIMIT)
+    .fetch_all(&mut *tx)
+    .await?;
+    let mut loader = ResponseLoader::new(
+        &state.user_cache,
+        &state.tags_cache,
+        &state.collaborators_cache,
+        &state.livestream_cache,
+    );
+    let livecomments = fill_livecomment_responses(
+        &mut tx,
+        livecomment_models,
+        &mut loader,
+        expand::Relation::Embed,
+        expand::Relation::Embed,
+        &mut Included::default(),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    livecomments
+        .into_iter()
+        .map(|livecomment| {
+            let data = serde_json::to_string(&livecomment)
+                .map_err(|e| Error::InternalServerError(e.to_string()))?;
+            Ok(std::sync::Arc::new(livestream_hub::LivestreamEvent {
+                kind: livestream_hub::EventKind::Livecomment,
+                id: livecomment.id,
+                data,
+            }))
+        })
+        .collect()
+}
+
+/// 遅れたストリームが DB から読み直す id の幅。コミットの順が id の順と前後しても、
+/// この幅に収まっていれば読み直しで拾える
+const LIVECOMMENT_STREAM_LOOKBACK: i64 = 1000;
+
+struct LivecommentStream {
+    state: AppState,
+    livestream_id: i64,
+    receiver: tokio::sync::broadcast::Receiver<std::sync::Arc<livestream_hub::LivestreamEvent>>,
+    /// 接続時点の Last-Event-ID (なければ最新の id)。これ以下の id は読み直さない
+    floor: i64,
+    /// 送ったライブコメントの id の最大値
+    max_id: i64,
+    /// 最近送ったライブコメントの id。ハブからの通知と DB からの読み直しで同じものを二度送らない
+    delivered: std::collections::HashSet<i64>,
+    delivered_order: std::collections::VecDeque<i64>,
+    pending: std::collections::VecDeque<std::sync::Arc<livestream_hub::LivestreamEvent>>,
+    /// 前回 DB から読み込んだ際に上限に達していれば、通知を待たずにこの id より後を読み込む
+    has_more: Option<i64>,
+}
+
+impl LivecommentStream {
+    async fn next(&mut self) -> Option<std::sync::Arc<livestream_hub::LivestreamEvent>> {
+        use tokio::sync::broadcast::error::RecvError;
+
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                if self.mark_delivered(event.id) {
+                    return Some(event);
+                }
+                continue;
+            }
+            if let Some(after_id) = self.has_more.take() {
+                self.reload(after_id).await?;
+                continue;
+            }
+            // ハブからの通知はコミットが終わった順に届くので id の順とは限らないが、そのまま送る
+            match self.receiver.recv().await {
+                Ok(event) => {
+                    if event.kind == livestream_hub::EventKind::Livecomment {
+                        self.pending.push_back(event);
+                    }
+                }
+                // 取りこぼした分は、前後してコミットされたものも含めて DB から読み直す
+                Err(RecvError::Lagged(n)) => {
+                    tracing::warn!("livecomment stream lagged behind by {} events", n);
+                    self.reload((self.max_id - LIVECOMMENT_STREAM_LOOKBACK).max(self.floor))
+                        .await?;
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
     }
 
-    let livecomment_models: Vec<LivecommentModel> = sqlx::query_as(&query)
-        .bind(livestream_id)
-        .fetch_all(&mut *tx)
-        .await?;
+    /// まだ送っていなければ送ったことにして true を返す
+    fn mark_delivered(&mut self, id: i64) -> bool {
+        if !self.delivered.insert(id) {
+            return false;
+        }
+        self.delivered_order.push_back(id);
+        // 読み直す幅に含まれうる id は全て覚えておく
+        if self.delivered_order.len() > 2 * LIVECOMMENT_STREAM_LOOKBACK as usize {
+            if let Some(oldest) = self.delivered_order.pop_front() {
+                self.delivered.remove(&oldest);
+            }
+        }
+        self.max_id = self.max_id.max(id);
+        true
+    }
 
-    let mut livecomments = Vec::with_capacity(livecomment_models.len());
-    for livecomment_model in livecomment_models {
-        let livecomment = fill_livecomment_response(
-            &mut tx,
-            livecomment_model,
-            &user_cache,
-            &tags_cache,
-            &livestream_cache,
+    async fn load(&mut self, after_id: i64) -> Result<(), Error> {
+        let events = load_livecomment_events(&self.state, self.livestream_id, after_id).await?;
+        if events.len() as i64 >= LIVECOMMENT_STREAM_BACKLOG_LIMIT {
+            self.has_more = events.last().map(|event| event.id);
+        }
+        self.pending.extend(events);
+        Ok(())
+    }
+
+    /// 読み込みに失敗した場合はストリームを終了する
+    async fn reload(&mut self, after_id: i64) -> Option<()> {
+        self.load(after_id)
+            .await
+            .map_err(|e| tracing::error!("failed to load livecomments for stream: {:?}", e))
+            .ok()
+    }
+}
+
+// ライブコメントの新着を Server-Sent Events で配信する
+// GET /api/livestream/:livestream_id/livecomment/stream
+async fn stream_livecomments_handler(
+    State(state): State<AppState>,
+    LivestreamPath { livestream_id, .. }: LivestreamPath,
+    headers: axum::http::HeaderMap,
+) -> Result<
+    axum::response::sse::Sse<
+        impl futures_util::Stream<Item = Result<axum::response::sse::Event, std::convert::Infallible>>,
+    >,
+    Error,
+> {
+    use futures_util::StreamExt as _;
+
+    let last_event_id: Option<i64> = headers
+        .get("last-event-id")
+        .map(|v| {
+            v.to_str()
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .ok_or(Error::BadRequest("invalid Last-Event-ID".into()))
+        })
+        .transpose()?;
+
+    // 取りこぼしを読む前に購読を始めておき、重複分は id で除外する
+    let receiver = state.livestream_hub.subscribe(livestream_id);
+
+    let floor = match last_event_id {
+        Some(last_event_id) => last_event_id,
+        None => sqlx::query_scalar(
+            "SELECT CAST(COALESCE(MAX(id), 0) AS SIGNED) FROM livecomments WHERE livestream_id = ?",
         )
-        .await?;
-        livecomments.push(livecomment);
+        .bind(livestream_id)
+        .fetch_one(&mut *state.pool.acquire().await?)
+        .await?,
+    };
+
+    let mut stream = LivecommentStream {
+        state,
+        livestream_id,
+        receiver,
+        floor,
+        max_id: floor,
+        delivered: Default::default(),
+        delivered_order: Default::default(),
+        pending: Default::default(),
+        has_more: None,
+    };
+    if last_event_id.is_some() {
+        stream.load(floor).await?;
     }
+    let stream = futures_util::stream::unfold(stream, |mut stream| async move {
+        let event = stream.next().await?;
+        Some((event, stream))
+    })
+    .map(|event| {
+        Ok(axum::response::sse::Event::default()
+            .id(event.id.to_string())
+            .event(event.kind.as_str())
+            .data(&event.data))
+    });
 
-    tx.commit().await?;
+    Ok(axum::response::sse::Sse::new(stream).keep_alive(axum::response::sse::KeepAlive::default()))
+}
+
+/// WebSocket の接続ごとの送信待ちメッセージ数の上限。溢れた接続は切断する
+const WS_SEND_QUEUE_CAPACITY: usize = 64;
 
-    Ok(axum::Json(livecomments))
+/// クライアントから送られるメッセージ
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WsClientMessage {
+    Livecomment {
+        request_id: Option<String>,
+        #[serde(flatten)]
+        req: PostLivecommentRequest,
+    },
+    Reaction {
+        request_id: Option<String>,
+        #[serde(flatten)]
+        req: PostReactionRequest,
+    },
 }
 
-async fn get_ngwords(
-    State(AppState { pool, .. }): State<AppState>,
-    jar: SignedCookieJar,
-    Path((livestream_id,)): Path<(i64,)>,
-) -> Result<axum::Json<Vec<NgWord>>, Error> {
-    verify_user_session(&jar).await?;
+/// 投稿に対する応答。投稿されたライブコメント・リアクション自体は他の接続と同様にイベントとして届く
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WsReply {
+    Ack {
+        request_id: Option<String>,
+        id: i64,
+    },
+    Error {
+        request_id: Option<String>,
+        error: String,
+    },
+}
 
+// ライブコメント・リアクションの送受信
+// GET /api/livestream/:livestream_id/ws
+async fn livestream_ws_handler(
+    State(state): State<AppState>,
+    jar: SignedCookieJar,
+    LivestreamPath { livestream_id, .. }: LivestreamPath,
+    ws: axum::extract::ws::WebSocketUpgrade,
+) -> Result<axum::response::Response, Error> {
     let cookie = jar.get(DEFAULT_SESSION_ID_KEY).ok_or(Error::SessionError)?;
     let sess = CookieStore::new()
         .load_session(cookie.value().to_owned())
         .await?
         .ok_or(Error::SessionError)?;
     let user_id: i64 = sess.get(DEFAULT_USER_ID_KEY).ok_or(Error::SessionError)?;
+    let session_expires: i64 = sess
+        .get(DEFUALT_SESSION_EXPIRES_KEY)
+        .ok_or(Error::SessionError)?;
+
+    Ok(ws.on_upgrade(move |socket| {
+        serve_livestream_ws(state, socket, user_id, session_expires, livestream_id)
+    }))
+}
+
+async fn serve_livestream_ws(
+    state: AppState,
+    socket: axum::extract::ws::WebSocket,
+    user_id: i64,
+    session_expires: i64,
+    livestream_id: i64,
+) {
+    use axum::extract::ws::Message;
+    use futures_util::{SinkExt as _, StreamExt as _};
+    use tokio::sync::broadcast::error::RecvError;
+
+    let (mut sink, mut stream) = socket.split();
+    let (queue, mut queue_rx) = tokio::sync::mpsc::channel::<Message>(WS_SEND_QUEUE_CAPACITY);
+    let writer = tokio::spawn(async move {
+        while let Some(message) = queue_rx.recv().await {
+            if sink.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut events = state.livestream_hub.subscribe(livestream_id);
+    loop {
+        let message = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => Message::Text(format!(
+                    r#"{{"type":"{}","id":{},"data":{}}}"#,
+                    event.kind.as_str(),
+                    event.id,
+                    event.data
+                )),
+                Err(RecvError::Lagged(n)) => {
+                    tracing::warn!("websocket for livestream {} lagged behind by {} events", livestream_id, n);
+                    break;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = stream.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = handle_ws_message(&state, user_id, session_expires, livestream_id, &text).await;
+                    match serde_json::to_string(&reply) {
+                        Ok(reply) => Message::Text(reply),
+                        Err(e) => {
+                            tracing::error!("failed to serialize websocket reply: {}", e);
+                            break;
+                        }
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        // 送信が追いつかないクライアントは待たずに切断する
+        if queue.try_send(message).is_err() {
+            tracing::warn!(
+                "dropping slow websocket consumer for livestream {}",
+                livestream_id
+            );
+            writer.abort();
+            return;
+        }
+    }
+    drop(queue);
+    let _ = writer.await;
+}
+
+async fn handle_ws_message(
+    state: &AppState,
+    user_id: i64,
+    session_expires: i64,
+    livestream_id: i64,
+    text: &str,
+) -> WsReply {
+    let message: WsClientMessage = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(e) => {
+            return WsReply::Error {
+                request_id: None,
+                error: format!("invalid message: {e}"),
+            }
+        }
+    };
+    let (request_id, result) = if Utc::now().timestamp() > session_expires {
+        let request_id = match message {
+            WsClientMessage::Livecomment { request_id, .. }
+            | WsClientMessage::Reaction { request_id, .. } => request_id,
+        };
+        (
+            request_id,
+            Err(Error::Unauthorized("session has expired".into())),
+        )
+    } else {
+        match message {
+            WsClientMessage::Livecomment { request_id, req } => (
+                request_id,
+                create_livecomment(state, user_id, livestream_id, req)
+                    .await
+                    .map(|livecomment| livecomment.id),
+            ),
+            WsClientMessage::Reaction { request_id, req } => (
+                request_id,
+                create_reaction(state, user_id, livestream_id, req)
+                    .await
+                    .map(|reaction| reaction.id),
+            ),
+        }
+    };
+    match result {
+        Ok(id) => WsReply::Ack { request_id, id },
+        Err(e) => WsReply::Error {
+            request_id,
+            error: e.to_string(),
+        },
+    }
+}
 
+async fn get_ngwords(
+    State(AppState { pool, .. }): State<AppState>,
+    ModeratedLivestreamPath {
+        livestream_id,
+        owner_id,
+        ..
+    }: ModeratedLivestreamPath,
+) -> Result<axum::Json<Vec<NgWord>>, Error> {
     let mut tx = pool.begin().await?;
 
     let ng_words: Vec<NgWord> = sqlx::query_as(
         "SELECT * FROM ng_words WHERE user_id = ? AND livestream_id = ? ORDER BY created_at DESC",
     )
-    .bind(user_id)
+    .bind(owner_id)
     .bind(livestream_id)
     .fetch_all(&mut *tx)
     .await?;
@@ -1180,18 +2913,28 @@ async fn get_ngwords(
     Ok(axum::Json(ng_words))
 }
 
-async fn post_livecomment_handler(
-    State(AppState {
-        pool,
-        user_cache,
-        tags_cache,
-        livestream_cache,
+// NGワードの削除
+// DELETE /api/livestream/:livestream_id/ngwords/:word_id
+async fn delete_ngword_handler(
+    State(state): State<AppState>,
+    ModeratedLivestreamPath {
+        livestream_id,
+        owner_id,
         ..
-    }): State<AppState>,
+    }: ModeratedLivestreamPath,
+    Path((_, word_id)): Path<(i64, i64)>,
+) -> Result<StatusCode, Error> {
+    delete_ng_word(&state, owner_id, livestream_id, word_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// 配信者の NG ワードの一覧。全ての配信に適用されるもの (livestream_id = 0) と各配信のものを含む
+// GET /api/user/me/ngwords
+async fn get_default_ngwords_handler(
+    State(AppState { pool, .. }): State<AppState>,
     jar: SignedCookieJar,
-    Path((livestream_id,)): Path<(i64,)>,
-    axum::Json(req): axum::Json<PostLivecommentRequest>,
-) -> Result<(StatusCode, axum::Json<Livecomment>), Error> {
+) -> Result<axum::Json<Vec<NgWord>>, Error> {
     verify_user_session(&jar).await?;
 
     let cookie = jar.get(DEFAULT_SESSION_ID_KEY).ok_or(Error::SessionError)?;
@@ -1203,38 +2946,437 @@ async fn post_livecomment_handler(
 
     let mut tx = pool.begin().await?;
 
-    let livestream_model: LivestreamModel = livestream_cache
-        .get_or_insert(&mut tx, livestream_id)
-        .await
-        .ok_or(Error::NotFound("livestream not found".into()))?;
+    let ng_words: Vec<NgWord> = sqlx::query_as(
+        "SELECT * FROM ng_words WHERE user_id = ? ORDER BY livestream_id ASC, created_at DESC",
+    )
+    .bind(user_id)
+    .fetch_all(&mut *tx)
+    .await?;
 
-    // スパム判定
-    let ngwords: Vec<NgWord> =
-        sqlx::query_as("SELECT id, user_id, livestream_id, word FROM ng_words WHERE user_id = ? AND livestream_id = ?")
-            .bind(livestream_model.user_id)
-            .bind(livestream_model.id)
+    tx.commit().await?;
+
+    Ok(axum::Json(ng_words))
+}
+
+// 配信者の全ての配信に適用されるNGワードの登録
+// POST /api/user/me/ngwords
+async fn post_default_ngword_handler(
+    State(AppState {
+        pool,
+        ng_word_matcher_cache,
+        ..
+    }): State<AppState>,
+    jar: SignedCookieJar,
+    axum::Json(req): axum::Json<ModerateRequest>,
+) -> Result<(StatusCode, axum::Json<ModerateResponse>), Error> {
+    verify_user_session(&jar).await?;
+
+    let cookie = jar.get(DEFAULT_SESSION_ID_KEY).ok_or(Error::SessionError)?;
+    let sess = CookieStore::new()
+        .load_session(cookie.value().to_owned())
+        .await?
+        .ok_or(Error::SessionError)?;
+    let user_id: i64 = sess.get(DEFAULT_USER_ID_KEY).ok_or(Error::SessionError)?;
+
+    let mut tx = pool.begin().await?;
+
+    let (word_id, deleted_livecomments) = insert_ng_word(
+        &mut tx,
+        user_id,
+        DEFAULT_NG_WORDS_LIVESTREAM_ID,
+        &req.ng_word,
+        req.mode,
+        Utc::now().timestamp(),
+    )
+    .await?;
+    let livestream_ids =
+        ng_word_livestream_ids(&mut tx, user_id, DEFAULT_NG_WORDS_LIVESTREAM_ID).await?;
+
+    tx.commit().await?;
+
+    for livestream_id in livestream_ids {
+        ng_word_matcher_cache.invalidate(&livestream_id).await;
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        axum::Json(ModerateResponse {
+            word_id,
+            deleted_livecomments,
+        }),
+    ))
+}
+
+// 配信者の全ての配信に適用されるNGワードの削除
+// DELETE /api/user/me/ngwords/:word_id
+async fn delete_default_ngword_handler(
+    State(state): State<AppState>,
+    jar: SignedCookieJar,
+    Path((word_id,)): Path<(i64,)>,
+) -> Result<StatusCode, Error> {
+    verify_user_session(&jar).await?;
+
+    let cookie = jar.get(DEFAULT_SESSION_ID_KEY).ok_or(Error::SessionError)?;
+    let sess = CookieStore::new()
+        .load_session(cookie.value().to_owned())
+        .await?
+        .ok_or(Error::SessionError)?;
+    let user_id: i64 = sess.get(DEFAULT_USER_ID_KEY).ok_or(Error::SessionError)?;
+
+    delete_ng_word(&state, user_id, DEFAULT_NG_WORDS_LIVESTREAM_ID, word_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// 配信の所有者であることは呼び出し側で検証しておく。既に非表示にしたライブコメントは元に戻さない
+async fn delete_ng_word(
+    AppState {
+        pool,
+        ng_word_matcher_cache,
+        ..
+    }: &AppState,
+    user_id: i64,
+    livestream_id: i64,
+    word_id: i64,
+) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+
+    let rs = sqlx::query("DELETE FROM ng_words WHERE id = ? AND user_id = ? AND livestream_id = ?")
+        .bind(word_id)
+        .bind(user_id)
+        .bind(livestream_id)
+        .execute(&mut *tx)
+        .await?;
+    if rs.rows_affected() == 0 {
+        return Err(Error::NotFound("ng word not found".into()));
+    }
+    let livestream_ids = ng_word_livestream_ids(&mut tx, user_id, livestream_id).await?;
+
+    tx.commit().await?;
+
+    for livestream_id in livestream_ids {
+        ng_word_matcher_cache.invalidate(&livestream_id).await;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum NgWordExportFormat {
+    /// NgWord の配列
+    #[default]
+    Json,
+    /// 1 行に 1 つの NG ワード。照合方法は含まれない
+    Text,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct NgWordExportQuery {
+    #[serde(default)]
+    format: NgWordExportFormat,
+}
+
+// NGワードのエクスポート
+// GET /api/livestream/:livestream_id/ngwords/export?format=json|text
+async fn export_ngwords_handler(
+    State(AppState { pool, .. }): State<AppState>,
+    ModeratedLivestreamPath {
+        livestream_id,
+        owner_id,
+        ..
+    }: ModeratedLivestreamPath,
+    Query(NgWordExportQuery { format }): Query<NgWordExportQuery>,
+) -> Result<axum::response::Response, Error> {
+    let mut tx = pool.begin().await?;
+    let res = export_ng_words(&mut tx, owner_id, livestream_id, format).await?;
+    tx.commit().await?;
+
+    Ok(res)
+}
+
+// 配信者の全ての配信に適用されるNGワードのエクスポート
+// GET /api/user/me/ngwords/export?format=json|text
+async fn export_default_ngwords_handler(
+    State(AppState { pool, .. }): State<AppState>,
+    jar: SignedCookieJar,
+    Query(NgWordExportQuery { format }): Query<NgWordExportQuery>,
+) -> Result<axum::response::Response, Error> {
+    verify_user_session(&jar).await?;
+
+    let cookie = jar.get(DEFAULT_SESSION_ID_KEY).ok_or(Error::SessionError)?;
+    let sess = CookieStore::new()
+        .load_session(cookie.value().to_owned())
+        .await?
+        .ok_or(Error::SessionError)?;
+    let user_id: i64 = sess.get(DEFAULT_USER_ID_KEY).ok_or(Error::SessionError)?;
+
+    let mut tx = pool.begin().await?;
+    let res = export_ng_words(&mut tx, user_id, DEFAULT_NG_WORDS_LIVESTREAM_ID, format).await?;
+    tx.commit().await?;
+
+    Ok(res)
+}
+
+async fn export_ng_words(
+    tx: &mut MySqlConnection,
+    user_id: i64,
+    livestream_id: i64,
+    format: NgWordExportFormat,
+) -> Result<axum::response::Response, Error> {
+    use axum::response::IntoResponse as _;
+
+    // インポートし直したときに元の順序になるよう登録順に並べる
+    let ng_words: Vec<NgWord> = sqlx::query_as(
+        "SELECT * FROM ng_words WHERE user_id = ? AND livestream_id = ? ORDER BY id ASC",
+    )
+    .bind(user_id)
+    .bind(livestream_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    match format {
+        NgWordExportFormat::Json => Ok(axum::Json(ng_words).into_response()),
+        NgWordExportFormat::Text => {
+            let mut body = String::new();
+            for ng_word in &ng_words {
+                body.push_str(&ng_word.word);
+                body.push('\n');
+            }
+            Ok((
+                [(
+                    axum::http::header::CONTENT_TYPE,
+                    "text/plain; charset=utf-8",
+                )],
+                body,
+            )
+                .into_response())
+        }
+    }
+}
+
+/// エクスポートした NgWord をそのまま読み込めるよう、word と mode 以外のフィールドは無視する
+#[derive(Debug, serde::Deserialize)]
+struct NgWordImportEntry {
+    word: String,
+    #[serde(default)]
+    mode: ngword::Mode,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct NgWordImportResponse {
+    imported: i64,
+    /// 登録済み、または同じ内容で重複していたもの
+    skipped: i64,
+    /// 非表示にしたライブコメント数
+    deleted_livecomments: i64,
+}
+
+/// Content-Type が application/json なら NgWord の配列、text/plain なら 1 行に 1 つの NG ワード (substring)
+fn parse_ng_word_import(
+    headers: &axum::http::HeaderMap,
+    body: &str,
+) -> Result<Vec<NgWordImportEntry>, Error> {
+    let content_type = headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let entries = match mime.as_str() {
+        "application/json" => serde_json::from_str(body)
+            .map_err(|e| Error::BadRequest(format!("failed to parse ng words: {}", e).into()))?,
+        // 行頭・行末の空白も NG ワードの一部として扱い、空行だけを読み飛ばす
+        "text/plain" => body
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| NgWordImportEntry {
+                word: line.to_owned(),
+                mode: ngword::Mode::default(),
+            })
+            .collect(),
+        _ => {
+            return Err(Error::BadRequest(
+                "Content-Type must be application/json or text/plain".into(),
+            ))
+        }
+    };
+    // 空の NG ワードなどは登録時に compile_ng_word で弾く
+    Ok(entries)
+}
+
+// NGワードの一括登録
+// POST /api/livestream/:livestream_id/ngwords/import
+async fn import_ngwords_handler(
+    State(state): State<AppState>,
+    ModeratedLivestreamPath {
+        livestream_id,
+        owner_id,
+        ..
+    }: ModeratedLivestreamPath,
+    headers: axum::http::HeaderMap,
+    body: String,
+) -> Result<(StatusCode, axum::Json<NgWordImportResponse>), Error> {
+    let entries = parse_ng_word_import(&headers, &body)?;
+    let res = import_ng_words(&state, owner_id, livestream_id, entries).await?;
+
+    Ok((StatusCode::CREATED, axum::Json(res)))
+}
+
+// 配信者の全ての配信に適用されるNGワードの一括登録
+// POST /api/user/me/ngwords/import
+async fn import_default_ngwords_handler(
+    State(state): State<AppState>,
+    jar: SignedCookieJar,
+    headers: axum::http::HeaderMap,
+    body: String,
+) -> Result<(StatusCode, axum::Json<NgWordImportResponse>), Error> {
+    verify_user_session(&jar).await?;
+
+    let cookie = jar.get(DEFAULT_SESSION_ID_KEY).ok_or(Error::SessionError)?;
+    let sess = CookieStore::new()
+        .load_session(cookie.value().to_owned())
+        .await?
+        .ok_or(Error::SessionError)?;
+    let user_id: i64 = sess.get(DEFAULT_USER_ID_KEY).ok_or(Error::SessionError)?;
+
+    let entries = parse_ng_word_import(&headers, &body)?;
+    let res = import_ng_words(&state, user_id, DEFAULT_NG_WORDS_LIVESTREAM_ID, entries).await?;
+
+    Ok((StatusCode::CREATED, axum::Json(res)))
+}
+
+/// 配信の所有者であることは呼び出し側で検証しておく
+async fn import_ng_words(
+    AppState {
+        pool,
+        ng_word_matcher_cache,
+        ..
+    }: &AppState,
+    user_id: i64,
+    livestream_id: i64,
+    entries: Vec<NgWordImportEntry>,
+) -> Result<NgWordImportResponse, Error> {
+    let mut tx = pool.begin().await?;
+
+    let registered: Vec<(String, String)> =
+        sqlx::query_as("SELECT word, mode FROM ng_words WHERE user_id = ? AND livestream_id = ?")
+            .bind(user_id)
+            .bind(livestream_id)
             .fetch_all(&mut *tx)
             .await?;
-    for ngword in &ngwords {
-        let query = r#"
-        SELECT COUNT(*)
-        FROM
-        (SELECT ? AS text) AS texts
-        INNER JOIN
-        (SELECT CONCAT('%', ?, '%')	AS pattern) AS patterns
-        ON texts.text LIKE patterns.pattern;
-        "#;
-        let hit_spam: i64 = sqlx::query_scalar(query)
-            .bind(&req.comment)
-            .bind(&ngword.word)
-            .fetch_one(&mut *tx)
-            .await?;
-        tracing::info!("[hit_spam={}] comment = {}", hit_spam, req.comment);
-        if hit_spam >= 1 {
-            return Err(Error::BadRequest(
-                "このコメントがスパム判定されました".into(),
-            ));
+    let mut registered: std::collections::HashSet<(String, String)> =
+        registered.into_iter().collect();
+
+    let created_at = Utc::now().timestamp();
+    let mut res = NgWordImportResponse {
+        imported: 0,
+        skipped: 0,
+        deleted_livecomments: 0,
+    };
+    // (ng_words.id, NG ワード, 照合方法, コンパイル済みの NG ワード)
+    let mut inserted = Vec::new();
+    for NgWordImportEntry { word, mode } in entries {
+        if !registered.insert((word.clone(), mode.as_str().to_owned())) {
+            res.skipped += 1;
+            continue;
         }
+        let matcher = compile_ng_word(&word, mode)?;
+        let word_id =
+            insert_ng_word_row(&mut tx, user_id, livestream_id, &word, mode, created_at).await?;
+        inserted.push((word_id, word, mode, matcher));
+        res.imported += 1;
+    }
+
+    // ライブコメントの走査は全ての NG ワードをまとめて 1 回で済ませ、
+    // 一致したライブコメントは先に登録した NG ワードによるものとして記録する
+    if !inserted.is_empty() {
+        let matcher = ngword::NgWordMatcher::new(
+            inserted
+                .iter()
+                .map(|(_, word, mode, _)| (word.as_str(), *mode)),
+        )
+        .map_err(|e| Error::BadRequest(format!("invalid ng word: {}", e).into()))?;
+        let hit = matching_livecomments(&mut tx, user_id, livestream_id, &matcher).await?;
+        res.deleted_livecomments = hit.len() as i64;
+
+        let mut hit_by_word: HashMap<i64, Vec<i64>> = HashMap::new();
+        for livecomment in hit {
+            if let Some((word_id, ..)) = inserted
+                .iter()
+                .find(|(.., matcher)| matcher.is_match(&livecomment.comment))
+            {
+                hit_by_word
+                    .entry(*word_id)
+                    .or_default()
+                    .push(livecomment.id);
+            }
+        }
+        for (word_id, livecomment_ids) in hit_by_word {
+            hide_livecomments(&mut tx, word_id, &livecomment_ids, created_at).await?;
+        }
+    }
+    let livestream_ids = ng_word_livestream_ids(&mut tx, user_id, livestream_id).await?;
+
+    tx.commit().await?;
+
+    for livestream_id in livestream_ids {
+        ng_word_matcher_cache.invalidate(&livestream_id).await;
+    }
+
+    Ok(res)
+}
+
+async fn post_livecomment_handler(
+    State(state): State<AppState>,
+    LivestreamPath {
+        livestream_id,
+        user_id,
+        ..
+    }: LivestreamPath,
+    axum::Json(req): axum::Json<PostLivecommentRequest>,
+) -> Result<(StatusCode, axum::Json<Livecomment>), Error> {
+    let livecomment = create_livecomment(&state, user_id, livestream_id, req).await?;
+
+    Ok((StatusCode::CREATED, axum::Json(livecomment)))
+}
+
+/// ライブコメントを投稿する。HTTP と WebSocket の両方から使う
+async fn create_livecomment(
+    AppState {
+        pool,
+        user_cache,
+        tags_cache,
+        collaborators_cache,
+        livestream_cache,
+        ng_word_matcher_cache,
+        livestream_hub,
+        ..
+    }: &AppState,
+    user_id: i64,
+    livestream_id: i64,
+    req: PostLivecommentRequest,
+) -> Result<Livecomment, Error> {
+    let mut tx = pool.begin().await?;
+
+    let livestream_model: LivestreamModel = livestream_cache
+        .get_or_insert(&mut tx, livestream_id)
+        .await?
+        .ok_or(Error::NotFound("livestream not found".into()))?;
+
+    // スパム判定
+    let ng_words = ng_word_matcher_cache
+        .get_or_insert(&mut tx, livestream_model.id)
+        .await?;
+    if ng_words.is_match(&req.comment) {
+        tracing::info!("[hit_spam] comment = {}", req.comment);
+        return Err(Error::BadRequest(
+            "このコメントがスパム判定されました".into(),
+        ));
     }
 
     let now = Utc::now().timestamp();
@@ -1261,15 +3403,23 @@ async fn post_livecomment_handler(
             tip: req.tip,
             created_at: now,
         },
-        &user_cache,
-        &tags_cache,
-        &livestream_cache,
+        user_cache,
+        tags_cache,
+        collaborators_cache,
+        livestream_cache,
     )
     .await?;
 
     tx.commit().await?;
 
-    Ok((StatusCode::CREATED, axum::Json(livecomment)))
+    livestream_hub.publish(
+        livestream_id,
+        livestream_hub::EventKind::Livecomment,
+        livecomment.id,
+        &livecomment,
+    );
+
+    Ok(livecomment)
 }
 
 async fn report_livecomment_handler(
@@ -1277,14 +3427,17 @@ async fn report_livecomment_handler(
         pool,
         user_cache,
         tags_cache,
+        collaborators_cache,
         livestream_cache,
         ..
     }): State<AppState>,
     jar: SignedCookieJar,
-    Path((livestream_id, livecomment_id)): Path<(i64, i64)>,
+    LivecommentPath {
+        livestream_id,
+        livecomment_id,
+        ..
+    }: LivecommentPath,
 ) -> Result<(StatusCode, axum::Json<LivecommentReport>), Error> {
-    verify_user_session(&jar).await?;
-
     let cookie = jar.get(DEFAULT_SESSION_ID_KEY).ok_or(Error::SessionError)?;
     let sess = CookieStore::new()
         .load_session(cookie.value().to_owned())
@@ -1294,109 +3447,392 @@ async fn report_livecomment_handler(
 
     let mut tx = pool.begin().await?;
 
-    let _: LivestreamModel = livestream_cache
-        .get_or_insert(&mut tx, livestream_id)
-        .await
-        .ok_or(Error::NotFound("livestream not found".into()))?;
-
-    let _: LivecommentModel = sqlx::query_as("SELECT * FROM livecomments WHERE id = ?")
-        .bind(livecomment_id)
-        .fetch_optional(&mut *tx)
-        .await?
-        .ok_or(Error::NotFound("livecomment not found".into()))?;
-
+    // 同じユーザからの同じライブコメントへの報告は 1 件にまとめる。
+    // INSERT IGNORE は重複以外のエラーも警告にしてしまうので、重複時は何も更新しない UPDATE にする
     let now = Utc::now().timestamp();
-    let rs = sqlx::query(
-        "INSERT INTO livecomment_reports(user_id, livestream_id, livecomment_id, created_at) VALUES (?, ?, ?, ?)",
+    sqlx::query(
+        "INSERT INTO livecomment_reports(user_id, livestream_id, livecomment_id, status, created_at) VALUES (?, ?, ?, ?, ?) ON DUPLICATE KEY UPDATE id = id",
     )
     .bind(user_id)
     .bind(livestream_id)
     .bind(livecomment_id)
+    .bind(ReportStatus::Open.as_str())
     .bind(now)
     .execute(&mut *tx)
     .await?;
-    let report_id = rs.last_insert_id() as i64;
+
+    let report_model: LivecommentReportModel = sqlx::query_as(
+        "SELECT * FROM livecomment_reports WHERE user_id = ? AND livecomment_id = ?",
+    )
+    .bind(user_id)
+    .bind(livecomment_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    // 報告者数は重複した報告では変わらないので、何度判定しても結果は同じ
+    hide_reported_livecomment(&mut tx, livecomment_id, now).await?;
 
     let report = fill_livecomment_report_response(
         &mut tx,
-        LivecommentReportModel {
-            id: report_id,
-            user_id,
-            livestream_id,
-            livecomment_id,
-            created_at: now,
-        },
+        report_model,
         &user_cache,
         &tags_cache,
+        &collaborators_cache,
         &livestream_cache,
     )
     .await?;
 
     tx.commit().await?;
 
+    // 重複した報告でも初回と同じく 201 を返す
     Ok((StatusCode::CREATED, axum::Json(report)))
 }
 
+static REPORT_HIDE_THRESHOLD: OnceLock<Option<i64>> = OnceLock::new();
+/// `ISUCON13_REPORT_HIDE_THRESHOLD` を超える人数から報告されたライブコメントは自動で非表示にする。
+/// 未設定なら非表示にしない
+fn report_hide_threshold() -> Option<i64> {
+    *REPORT_HIDE_THRESHOLD.get_or_init(|| {
+        std::env::var("ISUCON13_REPORT_HIDE_THRESHOLD")
+            .ok()
+            .map(|v| v.parse().expect("report hide threshold must be an integer"))
+    })
+}
+
+/// 却下されていない報告の報告者数がしきい値を超えていれば非表示にする
+async fn hide_reported_livecomment(
+    tx: &mut MySqlConnection,
+    livecomment_id: i64,
+    now: i64,
+) -> Result<(), Error> {
+    let Some(threshold) = report_hide_threshold() else {
+        return Ok(());
+    };
+    let MysqlDecimal(reporters) = sqlx::query_scalar(
+        "SELECT COUNT(DISTINCT user_id) FROM livecomment_reports WHERE livecomment_id = ? AND status <> ?",
+    )
+    .bind(livecomment_id)
+    .bind(ReportStatus::Dismissed.as_str())
+    .fetch_one(&mut *tx)
+    .await?;
+    if reporters > threshold {
+        sqlx::query("UPDATE livecomments SET deleted_at = ? WHERE id = ? AND deleted_at IS NULL")
+            .bind(now)
+            .bind(livecomment_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct UpdateLivecommentReportRequest {
+    status: ReportStatus,
+}
+
+// (配信者、コラボレーター向け)ライブコメントの報告の対応状況を変更する
+// PATCH /api/livestream/:livestream_id/report/:report_id
+async fn update_livecomment_report_handler(
+    State(AppState {
+        pool,
+        user_cache,
+        tags_cache,
+        collaborators_cache,
+        livestream_cache,
+        ..
+    }): State<AppState>,
+    ModeratedLivestreamPath { livestream_id, .. }: ModeratedLivestreamPath,
+    Path((_, report_id)): Path<(i64, i64)>,
+    axum::Json(req): axum::Json<UpdateLivecommentReportRequest>,
+) -> Result<axum::Json<LivecommentReport>, Error> {
+    let mut tx = pool.begin().await?;
+
+    let mut report_model: LivecommentReportModel = sqlx::query_as(
+        "SELECT * FROM livecomment_reports WHERE id = ? AND livestream_id = ? FOR UPDATE",
+    )
+    .bind(report_id)
+    .bind(livestream_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(Error::NotFound("livecomment report not found".into()))?;
+
+    sqlx::query("UPDATE livecomment_reports SET status = ? WHERE id = ?")
+        .bind(req.status.as_str())
+        .bind(report_id)
+        .execute(&mut *tx)
+        .await?;
+    report_model.status = req.status;
+
+    if req.status == ReportStatus::Actioned {
+        sqlx::query("UPDATE livecomments SET deleted_at = ? WHERE id = ? AND deleted_at IS NULL")
+            .bind(Utc::now().timestamp())
+            .bind(report_model.livecomment_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let report = fill_livecomment_report_response(
+        &mut tx,
+        report_model,
+        &user_cache,
+        &tags_cache,
+        &collaborators_cache,
+        &livestream_cache,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(axum::Json(report))
+}
+
 #[derive(Debug, serde::Serialize)]
 struct ModerateResponse {
     word_id: i64,
+    /// 非表示にしたライブコメント数
+    deleted_livecomments: i64,
 }
 
 // NGワードを登録
 async fn moderate_handler(
     State(AppState {
         pool,
-        user_id_to_livestreams_cache,
+        ng_word_matcher_cache,
         ..
     }): State<AppState>,
-    jar: SignedCookieJar,
-    Path((livestream_id,)): Path<(i64,)>,
+    ModeratedLivestreamPath {
+        livestream_id,
+        owner_id,
+        ..
+    }: ModeratedLivestreamPath,
     axum::Json(req): axum::Json<ModerateRequest>,
 ) -> Result<(StatusCode, axum::Json<ModerateResponse>), Error> {
-    verify_user_session(&jar).await?;
+    let mut tx = pool.begin().await?;
 
-    let cookie = jar.get(DEFAULT_SESSION_ID_KEY).ok_or(Error::SessionError)?;
-    let sess = CookieStore::new()
-        .load_session(cookie.value().to_owned())
-        .await?
-        .ok_or(Error::SessionError)?;
-    let user_id: i64 = sess.get(DEFAULT_USER_ID_KEY).ok_or(Error::SessionError)?;
+    let (word_id, deleted_livecomments) = insert_ng_word(
+        &mut tx,
+        owner_id,
+        livestream_id,
+        &req.ng_word,
+        req.mode,
+        Utc::now().timestamp(),
+    )
+    .await?;
 
-    let mut tx = pool.begin().await?;
+    tx.commit().await?;
 
-    // 配信者自身の配信に対するmoderateなのかを検証
-    let _: LivestreamModel = user_id_to_livestreams_cache
-        .get_or_insert(&mut tx, user_id)
-        .await
+    ng_word_matcher_cache.invalidate(&livestream_id).await;
+
+    Ok((
+        StatusCode::CREATED,
+        axum::Json(ModerateResponse {
+            word_id,
+            deleted_livecomments,
+        }),
+    ))
+}
+
+/// 配信者の全ての配信に適用される NG ワードは livestream_id をこの値にして登録する
+const DEFAULT_NG_WORDS_LIVESTREAM_ID: i64 = 0;
+
+/// NG ワードで非表示にする候補の、まだ表示されているライブコメント
+#[derive(Debug, FromRow)]
+struct VisibleLivecomment {
+    id: i64,
+    comment: String,
+}
+
+/// matching_livecomments で一度に読み込むライブコメント数
+const VISIBLE_LIVECOMMENTS_BATCH_SIZE: i64 = 1000;
+
+/// まだ表示されているライブコメントのうち matcher に一致するものを返す。
+/// livestream_id が DEFAULT_NG_WORDS_LIVESTREAM_ID なら配信者の全ての配信のライブコメントが対象。
+/// 全件を一度にメモリに載せないよう id の順に少しずつ読み込む
+async fn matching_livecomments(
+    tx: &mut MySqlConnection,
+    user_id: i64,
+    livestream_id: i64,
+    matcher: &ngword::NgWordMatcher,
+) -> Result<Vec<VisibleLivecomment>, Error> {
+    let mut hit = Vec::new();
+    let mut last_id = 0;
+    loop {
+        let batch: Vec<VisibleLivecomment> = sqlx::query_as(
+            r#"
+            SELECT lc.id, lc.comment
+            FROM livecomments lc
+            INNER JOIN livestreams l ON l.id = lc.livestream_id
+            WHERE l.user_id = ? AND (l.id = ? OR ? = ?) AND lc.deleted_at IS NULL AND lc.id > ?
+            ORDER BY lc.id ASC
+            LIMIT ?
+            "#,
+        )
+        .bind(user_id)
+        .bind(livestream_id)
+        .bind(livestream_id)
+        .bind(DEFAULT_NG_WORDS_LIVESTREAM_ID)
+        .bind(last_id)
+        .bind(VISIBLE_LIVECOMMENTS_BATCH_SIZE)
+        .fetch_all(&mut *tx)
+        .await?;
+        let Some(last) = batch.last() else {
+            break;
+        };
+        last_id = last.id;
+        let done = (batch.len() as i64) < VISIBLE_LIVECOMMENTS_BATCH_SIZE;
+        hit.extend(
+            batch
+                .into_iter()
+                .filter(|livecomment| matcher.is_match(&livecomment.comment)),
+        );
+        if done {
+            break;
+        }
+    }
+    Ok(hit)
+}
+
+/// 空の NG ワードや、空文字列に一致する NG ワードは全てのライブコメントに一致してしまうので受け付けない
+fn compile_ng_word(word: &str, mode: ngword::Mode) -> Result<ngword::NgWordMatcher, Error> {
+    if word.is_empty() {
+        return Err(Error::BadRequest("ng word must not be empty".into()));
+    }
+    if mode == ngword::Mode::Normalized && ngword::normalize(word).is_empty() {
+        return Err(Error::BadRequest(
+            "ng word must not be empty after normalization".into(),
+        ));
+    }
+    let matcher = ngword::NgWordMatcher::new([(word, mode)])
+        .map_err(|e| Error::BadRequest(format!("invalid ng word: {}", e).into()))?;
+    if matcher.is_match("") {
+        return Err(Error::BadRequest(
+            "ng word must not match an empty comment".into(),
+        ));
+    }
+    Ok(matcher)
+}
+
+/// NG ワードに一致したライブコメントを非表示にする
+async fn hide_livecomments(
+    tx: &mut MySqlConnection,
+    word_id: i64,
+    livecomment_ids: &[i64],
+    deleted_at: i64,
+) -> Result<(), Error> {
+    if livecomment_ids.is_empty() {
+        return Ok(());
+    }
+    let mut query_builder = QueryBuilder::new("UPDATE livecomments SET deleted_at = ");
+    query_builder.push_bind(deleted_at);
+    query_builder.push(", deleted_by_ng_word_id = ");
+    query_builder.push_bind(word_id);
+    query_builder.push(" WHERE id IN (");
+    let mut separated = query_builder.separated(", ");
+    for id in livecomment_ids {
+        separated.push_bind(*id);
+    }
+    separated.push_unseparated(")");
+    query_builder.build().execute(&mut *tx).await?;
+    Ok(())
+}
+
+/// NG ワードを登録し、まだ表示されているライブコメントのうち NG ワードに一致するものを非表示にする。
+///
+/// (ng_words.id, 非表示にしたライブコメント数) を返す
+async fn insert_ng_word(
+    tx: &mut MySqlConnection,
+    user_id: i64,
+    livestream_id: i64,
+    word: &str,
+    mode: ngword::Mode,
+    created_at: i64,
+) -> Result<(i64, i64), Error> {
+    let matcher = compile_ng_word(word, mode)?;
+    let word_id = insert_ng_word_row(tx, user_id, livestream_id, word, mode, created_at).await?;
+
+    let hit: Vec<i64> = matching_livecomments(tx, user_id, livestream_id, &matcher)
+        .await?
         .into_iter()
-        .find(|model| model.id == livestream_id)
-        .ok_or(Error::BadRequest(
-            "A streamer can't moderate livestreams that other streamers own".into(),
-        ))?;
+        .map(|livecomment| livecomment.id)
+        .collect();
+    hide_livecomments(tx, word_id, &hit, created_at).await?;
 
-    let created_at = Utc::now().timestamp();
+    Ok((word_id, hit.len() as i64))
+}
+
+async fn insert_ng_word_row(
+    tx: &mut MySqlConnection,
+    user_id: i64,
+    livestream_id: i64,
+    word: &str,
+    mode: ngword::Mode,
+    created_at: i64,
+) -> Result<i64, Error> {
     let rs = sqlx::query(
-        "INSERT INTO ng_words(user_id, livestream_id, word, created_at) VALUES (?, ?, ?, ?)",
+        "INSERT INTO ng_words(user_id, livestream_id, word, mode, created_at) VALUES (?, ?, ?, ?, ?)",
     )
     .bind(user_id)
     .bind(livestream_id)
-    .bind(&req.ng_word)
+    .bind(word)
+    .bind(mode.as_str())
     .bind(created_at)
     .execute(&mut *tx)
     .await?;
-    let word_id = rs.last_insert_id() as i64;
+    Ok(rs.last_insert_id() as i64)
+}
 
-    sqlx::query("DELETE FROM livecomments WHERE comment LIKE CONCAT('%', ?, '%')")
-        .bind(req.ng_word)
-        .execute(&mut *tx)
-        .await?;
+/// NG ワードの変更でスパム判定が変わる配信。コミット後にこれらの NgWordMatcherCache を破棄する
+async fn ng_word_livestream_ids(
+    tx: &mut MySqlConnection,
+    user_id: i64,
+    livestream_id: i64,
+) -> Result<Vec<i64>, Error> {
+    if livestream_id != DEFAULT_NG_WORDS_LIVESTREAM_ID {
+        return Ok(vec![livestream_id]);
+    }
+    Ok(
+        sqlx::query_scalar("SELECT id FROM livestreams WHERE user_id = ?")
+            .bind(user_id)
+            .fetch_all(&mut *tx)
+            .await?,
+    )
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ModeratePreviewQuery {
+    ng_word: String,
+    #[serde(default)]
+    mode: ngword::Mode,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ModeratePreviewResponse {
+    deleted_livecomments: i64,
+}
+
+// NGワードを登録した場合に非表示になるライブコメント数
+// GET /api/livestream/:livestream_id/moderate/preview?ng_word=...&mode=...
+async fn moderate_preview_handler(
+    State(AppState { pool, .. }): State<AppState>,
+    ModeratedLivestreamPath {
+        livestream_id,
+        owner_id,
+        ..
+    }: ModeratedLivestreamPath,
+    Query(ModeratePreviewQuery { ng_word, mode }): Query<ModeratePreviewQuery>,
+) -> Result<axum::Json<ModeratePreviewResponse>, Error> {
+    let mut tx = pool.begin().await?;
+
+    let matcher = compile_ng_word(&ng_word, mode)?;
+    let deleted_livecomments = matching_livecomments(&mut tx, owner_id, livestream_id, &matcher)
+        .await?
+        .len() as i64;
 
     tx.commit().await?;
 
-    Ok((
-        StatusCode::CREATED,
-        axum::Json(ModerateResponse { word_id }),
-    ))
+    Ok(axum::Json(ModeratePreviewResponse {
+        deleted_livecomments,
+    }))
 }
 
 async fn fill_livecomment_response(
@@ -1404,28 +3840,59 @@ async fn fill_livecomment_response(
     livecomment_model: LivecommentModel,
     user_cache: &UserCache,
     tags_cache: &TagsCache,
+    collaborators_cache: &CollaboratorsCache,
     livestream_cache: &LivestreamCache,
-) -> sqlx::Result<Livecomment> {
-    let comment_owner = user_cache
-        .get_or_insert(tx, livecomment_model.user_id)
-        .await
-        .ok_or(sqlx::Error::RowNotFound)?;
+) -> Result<Livecomment, Error> {
+    let mut loader = ResponseLoader::new(
+        user_cache,
+        tags_cache,
+        collaborators_cache,
+        livestream_cache,
+    );
+    let livecomments = fill_livecomment_responses(
+        tx,
+        vec![livecomment_model],
+        &mut loader,
+        expand::Relation::Embed,
+        expand::Relation::Embed,
+        &mut Included::default(),
+    )
+    .await?;
+    Ok(livecomments.into_iter().next().unwrap())
+}
 
-    let livestream_model: LivestreamModel = livestream_cache
-        .get_or_insert(tx, livecomment_model.livestream_id)
-        .await
-        .ok_or(sqlx::Error::RowNotFound)?;
-    let livestream =
-        fill_livestream_response(&mut *tx, livestream_model, user_cache, tags_cache).await?;
+async fn fill_livecomment_responses(
+    tx: &mut MySqlConnection,
+    livecomment_models: Vec<LivecommentModel>,
+    loader: &mut ResponseLoader<'_>,
+    user_relation: expand::Relation,
+    livestream_relation: expand::Relation,
+    included: &mut Included,
+) -> Result<Vec<Livecomment>, Error> {
+    let user_ids = related_ids(user_relation, &livecomment_models, |m| m.user_id);
+    loader.load_users(&mut *tx, user_ids).await?;
+    let livestream_ids = related_ids(livestream_relation, &livecomment_models, |m| {
+        m.livestream_id
+    });
+    loader.load_livestreams(&mut *tx, livestream_ids).await?;
 
-    Ok(Livecomment {
-        id: livecomment_model.id,
-        user: comment_owner,
-        livestream,
-        comment: livecomment_model.comment,
-        tip: livecomment_model.tip,
-        created_at: livecomment_model.created_at,
-    })
+    livecomment_models
+        .into_iter()
+        .map(|livecomment_model| {
+            Ok(Livecomment {
+                id: livecomment_model.id,
+                user: loader.user(user_relation, livecomment_model.user_id, included)?,
+                livestream: loader.livestream(
+                    livestream_relation,
+                    livecomment_model.livestream_id,
+                    included,
+                )?,
+                comment: livecomment_model.comment,
+                tip: livecomment_model.tip,
+                created_at: livecomment_model.created_at,
+            })
+        })
+        .collect()
 }
 
 async fn fill_livecomment_report_response(
@@ -1433,33 +3900,95 @@ async fn fill_livecomment_report_response(
     report_model: LivecommentReportModel,
     user_cache: &UserCache,
     tags_cache: &TagsCache,
+    collaborators_cache: &CollaboratorsCache,
     livestream_cache: &LivestreamCache,
-) -> sqlx::Result<LivecommentReport> {
-    let reporter = user_cache
-        .get_or_insert(tx, report_model.user_id)
-        .await
-        .ok_or(sqlx::Error::RowNotFound)?;
-
-    let livecomment_model: LivecommentModel =
-        sqlx::query_as("SELECT * FROM livecomments WHERE id = ?")
-            .bind(report_model.livecomment_id)
-            .fetch_one(&mut *tx)
-            .await?;
-    let livecomment = fill_livecomment_response(
-        &mut *tx,
-        livecomment_model,
+) -> Result<LivecommentReport, Error> {
+    let mut loader = ResponseLoader::new(
         user_cache,
         tags_cache,
+        collaborators_cache,
         livestream_cache,
+    );
+    let reports = fill_livecomment_report_responses(
+        tx,
+        vec![report_model],
+        &mut loader,
+        &expand::Expand::full(),
+        &mut Included::default(),
     )
     .await?;
+    Ok(reports.into_iter().next().unwrap())
+}
 
-    Ok(LivecommentReport {
-        id: report_model.id,
-        reporter,
-        livecomment,
-        created_at: report_model.created_at,
-    })
+async fn fill_livecomment_report_responses(
+    tx: &mut MySqlConnection,
+    report_models: Vec<LivecommentReportModel>,
+    loader: &mut ResponseLoader<'_>,
+    expand: &expand::Expand,
+    included: &mut Included,
+) -> Result<Vec<LivecommentReport>, Error> {
+    let reporter_relation = expand.relation("reporter");
+    let reporter_ids = related_ids(reporter_relation, &report_models, |m| m.user_id);
+    loader.load_users(&mut *tx, reporter_ids).await?;
+
+    // 非表示にされたライブコメントの報告も返す
+    let livecomment_relation = expand.relation("livecomment");
+    let livecomment_ids = related_ids(livecomment_relation, &report_models, |m| m.livecomment_id);
+    let livecomment_models: Vec<LivecommentModel> = if livecomment_ids.is_empty() {
+        Vec::new()
+    } else {
+        let mut query_builder = QueryBuilder::new("SELECT * FROM livecomments WHERE id IN (");
+        let mut separated = query_builder.separated(", ");
+        for livecomment_id in livecomment_ids {
+            separated.push_bind(livecomment_id);
+        }
+        separated.push_unseparated(")");
+        query_builder.build_query_as().fetch_all(&mut *tx).await?
+    };
+    let livecomments: HashMap<i64, Livecomment> = fill_livecomment_responses(
+        tx,
+        livecomment_models,
+        loader,
+        expand.nested("user"),
+        expand.nested("livestream"),
+        included,
+    )
+    .await?
+    .into_iter()
+    .map(|livecomment| (livecomment.id, livecomment))
+    .collect();
+
+    report_models
+        .into_iter()
+        .map(|report_model| {
+            let livecomment_id = report_model.livecomment_id;
+            let get = || {
+                livecomments
+                    .get(&livecomment_id)
+                    .cloned()
+                    .ok_or(sqlx::Error::RowNotFound)
+            };
+            let livecomment = match livecomment_relation {
+                expand::Relation::Embed => expand::Expandable::Full(get()?),
+                expand::Relation::Id => {
+                    if let std::collections::btree_map::Entry::Vacant(entry) =
+                        included.livecomments.entry(livecomment_id)
+                    {
+                        entry.insert(get()?);
+                    }
+                    expand::Expandable::Id(livecomment_id)
+                }
+                expand::Relation::Omit => expand::Expandable::Id(livecomment_id),
+            };
+            Ok(LivecommentReport {
+                id: report_model.id,
+                reporter: loader.user(reporter_relation, report_model.user_id, included)?,
+                livecomment,
+                status: report_model.status,
+                created_at: report_model.created_at,
+            })
+        })
+        .collect()
 }
 
 #[derive(Debug, sqlx::FromRow)]
@@ -1475,20 +4004,16 @@ struct ReactionModel {
 struct Reaction {
     id: i64,
     emoji_name: String,
-    user: User,
-    livestream: Livestream,
+    user: expand::Expandable<User>,
+    livestream: expand::Expandable<Livestream>,
     created_at: i64,
 }
 
-#[derive(Debug, serde::Deserialize)]
-struct PostReactionRequest {
-    emoji_name: String,
-}
+const REACTION_FIELDS: &[&str] = &["id", "emoji_name", "user", "livestream", "created_at"];
 
 #[derive(Debug, serde::Deserialize)]
-struct GetReactionsQuery {
-    #[serde(default)]
-    limit: String,
+struct PostReactionRequest {
+    emoji_name: String,
 }
 
 async fn get_reactions_handler(
@@ -1496,68 +4021,84 @@ async fn get_reactions_handler(
         pool,
         user_cache,
         tags_cache,
+        collaborators_cache,
         livestream_cache,
         ..
     }): State<AppState>,
-    jar: SignedCookieJar,
-    Path((livestream_id,)): Path<(i64,)>,
-    Query(GetReactionsQuery { limit }): Query<GetReactionsQuery>,
-) -> Result<axum::Json<Vec<Reaction>>, Error> {
-    verify_user_session(&jar).await?;
+    LivestreamPath { livestream_id, .. }: LivestreamPath,
+    axum::extract::OriginalUri(uri): axum::extract::OriginalUri,
+    Query(page): Query<pagination::PageQuery>,
+    Query(expand): Query<expand::ExpandQuery>,
+) -> Result<(axum::http::HeaderMap, axum::response::Response), Error> {
+    let page = page.parse()?;
+    let expand = expand.parse(REACTION_FIELDS, &["user", "livestream"])?;
 
     let mut tx = pool.begin().await?;
 
-    let mut query =
-        "SELECT * FROM reactions WHERE livestream_id = ? ORDER BY created_at DESC".to_owned();
-    if !limit.is_empty() {
-        let limit: i64 = limit.parse().map_err(|_| Error::BadRequest("".into()))?;
-        query = format!("{} LIMIT {}", query, limit);
-    }
-
-    let reaction_models: Vec<ReactionModel> = sqlx::query_as(&query)
-        .bind(livestream_id)
-        .fetch_all(&mut *tx)
-        .await?;
+    let mut query_builder = QueryBuilder::new("SELECT * FROM reactions WHERE livestream_id = ");
+    query_builder.push_bind(livestream_id);
+    page.push_sql(&mut query_builder, Some("created_at"), "id");
+    let mut reaction_models: Vec<ReactionModel> =
+        query_builder.build_query_as().fetch_all(&mut *tx).await?;
+    let next = page.finish(&mut reaction_models, |m| pagination::Cursor {
+        created_at: m.created_at,
+        id: m.id,
+    });
 
-    let mut reactions = Vec::with_capacity(reaction_models.len());
-    for reaction_model in reaction_models {
-        let reaction = fill_reaction_response(
-            &mut tx,
-            reaction_model,
-            &user_cache,
-            &tags_cache,
-            &livestream_cache,
-        )
-        .await?;
-        reactions.push(reaction);
-    }
+    let mut loader = ResponseLoader::new(
+        &user_cache,
+        &tags_cache,
+        &collaborators_cache,
+        &livestream_cache,
+    );
+    let mut included = Included::default();
+    let reactions = fill_reaction_responses(
+        &mut tx,
+        reaction_models,
+        &mut loader,
+        expand.relation("user"),
+        expand.relation("livestream"),
+        &mut included,
+    )
+    .await?;
 
     tx.commit().await?;
 
-    Ok(axum::Json(reactions))
+    Ok((
+        pagination::link_headers(&uri, next),
+        expand.respond(reactions, included)?,
+    ))
 }
 
 async fn post_reaction_handler(
-    State(AppState {
-        pool,
-        user_cache,
-        tags_cache,
-        livestream_cache,
+    State(state): State<AppState>,
+    LivestreamPath {
+        livestream_id,
+        user_id,
         ..
-    }): State<AppState>,
-    jar: SignedCookieJar,
-    Path((livestream_id,)): Path<(i64,)>,
+    }: LivestreamPath,
     axum::Json(req): axum::Json<PostReactionRequest>,
 ) -> Result<(StatusCode, axum::Json<Reaction>), Error> {
-    verify_user_session(&jar).await?;
+    let reaction = create_reaction(&state, user_id, livestream_id, req).await?;
 
-    let cookie = jar.get(DEFAULT_SESSION_ID_KEY).ok_or(Error::SessionError)?;
-    let sess = CookieStore::new()
-        .load_session(cookie.value().to_owned())
-        .await?
-        .ok_or(Error::SessionError)?;
-    let user_id: i64 = sess.get(DEFAULT_USER_ID_KEY).ok_or(Error::SessionError)?;
+    Ok((StatusCode::CREATED, axum::Json(reaction)))
+}
 
+/// リアクションを投稿する。HTTP と WebSocket の両方から使う
+async fn create_reaction(
+    AppState {
+        pool,
+        user_cache,
+        tags_cache,
+        collaborators_cache,
+        livestream_cache,
+        livestream_hub,
+        ..
+    }: &AppState,
+    user_id: i64,
+    livestream_id: i64,
+    req: PostReactionRequest,
+) -> Result<Reaction, Error> {
     let mut tx = pool.begin().await?;
 
     let created_at = Utc::now().timestamp();
@@ -1580,15 +4121,23 @@ async fn post_reaction_handler(
             emoji_name: req.emoji_name,
             created_at,
         },
-        &user_cache,
-        &tags_cache,
-        &livestream_cache,
+        user_cache,
+        tags_cache,
+        collaborators_cache,
+        livestream_cache,
     )
     .await?;
 
     tx.commit().await?;
 
-    Ok((StatusCode::CREATED, axum::Json(reaction)))
+    livestream_hub.publish(
+        livestream_id,
+        livestream_hub::EventKind::Reaction,
+        reaction.id,
+        &reaction,
+    );
+
+    Ok(reaction)
 }
 
 async fn fill_reaction_response(
@@ -1596,27 +4145,56 @@ async fn fill_reaction_response(
     reaction_model: ReactionModel,
     user_cache: &UserCache,
     tags_cache: &TagsCache,
+    collaborators_cache: &CollaboratorsCache,
     livestream_cache: &LivestreamCache,
-) -> sqlx::Result<Reaction> {
-    let user = user_cache
-        .get_or_insert(&mut *tx, reaction_model.user_id)
-        .await
-        .ok_or(sqlx::Error::RowNotFound)?;
-
-    let livestream_model: LivestreamModel = livestream_cache
-        .get_or_insert(tx, reaction_model.livestream_id)
-        .await
-        .ok_or(sqlx::Error::RowNotFound)?;
-    let livestream =
-        fill_livestream_response(&mut *tx, livestream_model, user_cache, tags_cache).await?;
+) -> Result<Reaction, Error> {
+    let mut loader = ResponseLoader::new(
+        user_cache,
+        tags_cache,
+        collaborators_cache,
+        livestream_cache,
+    );
+    let reactions = fill_reaction_responses(
+        tx,
+        vec![reaction_model],
+        &mut loader,
+        expand::Relation::Embed,
+        expand::Relation::Embed,
+        &mut Included::default(),
+    )
+    .await?;
+    Ok(reactions.into_iter().next().unwrap())
+}
 
-    Ok(Reaction {
-        id: reaction_model.id,
-        emoji_name: reaction_model.emoji_name,
-        user,
-        livestream,
-        created_at: reaction_model.created_at,
-    })
+async fn fill_reaction_responses(
+    tx: &mut MySqlConnection,
+    reaction_models: Vec<ReactionModel>,
+    loader: &mut ResponseLoader<'_>,
+    user_relation: expand::Relation,
+    livestream_relation: expand::Relation,
+    included: &mut Included,
+) -> Result<Vec<Reaction>, Error> {
+    let user_ids = related_ids(user_relation, &reaction_models, |m| m.user_id);
+    loader.load_users(&mut *tx, user_ids).await?;
+    let livestream_ids = related_ids(livestream_relation, &reaction_models, |m| m.livestream_id);
+    loader.load_livestreams(&mut *tx, livestream_ids).await?;
+
+    reaction_models
+        .into_iter()
+        .map(|reaction_model| {
+            Ok(Reaction {
+                id: reaction_model.id,
+                emoji_name: reaction_model.emoji_name,
+                user: loader.user(user_relation, reaction_model.user_id, included)?,
+                livestream: loader.livestream(
+                    livestream_relation,
+                    reaction_model.livestream_id,
+                    included,
+                )?,
+                created_at: reaction_model.created_at,
+            })
+        })
+        .collect()
 }
 
 #[derive(Debug, sqlx::FromRow)]
@@ -1702,9 +4280,120 @@ struct PostIconResponse {
 
 const ICON_BASE_PATH: &str = "/home/isucon/webapp/public/icons";
 
+#[derive(Debug, serde::Deserialize)]
+struct GetIconQuery {
+    /// サムネイルの一辺の長さ。未指定なら元画像
+    size: Option<u32>,
+}
+
+// ユーザのアイコン取得API
+// GET /api/user/:username/icon
+async fn get_icon_handler(
+    State(AppState {
+        pool, icon_store, ..
+    }): State<AppState>,
+    Path((username,)): Path<(String,)>,
+    Query(GetIconQuery { size }): Query<GetIconQuery>,
+    if_none_match: Option<axum::TypedHeader<axum::headers::IfNoneMatch>>,
+) -> Result<axum::response::Response, Error> {
+    use axum::response::IntoResponse as _;
+
+    if let Some(size) = size {
+        if !icon_image::ICON_SIZES.contains(&size) {
+            return Err(Error::BadRequest(
+                format!("size must be one of {:?}", icon_image::ICON_SIZES).into(),
+            ));
+        }
+    }
+
+    let mut tx = pool.begin().await?;
+
+    let user_id: i64 = sqlx::query_scalar("SELECT id FROM users WHERE name = ?")
+        .bind(&username)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(Error::NotFound(
+            "not found user that has the given username".into(),
+        ))?;
+    let stored: Option<(String, String)> =
+        sqlx::query_as("SELECT icon_hash, format FROM icons WHERE user_id = ?")
+            .bind(user_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+    tx.commit().await?;
+
+    let (mut icon_hash, mut format) = match stored {
+        Some((icon_hash, format)) => (
+            icon_hash,
+            icon_image::IconFormat::try_from(format).map_err(Error::InternalServerError)?,
+        ),
+        None => (default_icon_hash(), icon_image::IconFormat::Jpeg),
+    };
+
+    // サムネイルがない (サムネイル生成前にアップロードされた、アイコン未設定) 場合は元画像を返す
+    let mut served_size = size;
+    let mut icon = match size {
+        Some(size) => {
+            icon_store
+                .get(
+                    &username,
+                    &icon_hash,
+                    icon_image::IconFormat::Jpeg,
+                    Some(size),
+                )
+                .await?
+        }
+        None => None,
+    };
+    if icon.is_none() {
+        served_size = None;
+        icon = icon_store.get(&username, &icon_hash, format, None).await?;
+    }
+    let icon = match icon {
+        Some(icon) => icon,
+        None => {
+            served_size = None;
+            icon_hash = default_icon_hash();
+            format = icon_image::IconFormat::Jpeg;
+            Box::new(tokio::fs::File::open(FALLBACK_IMAGE).await?)
+        }
+    };
+
+    // icon_hash は画像の sha256 なので strong ETag としてそのまま使える。サムネイルは常に JPEG
+    let (etag, content_type) = match served_size {
+        Some(size) => (
+            format!("\"{icon_hash}-{size}\""),
+            icon_image::IconFormat::Jpeg.content_type(),
+        ),
+        None => (format!("\"{icon_hash}\""), format.content_type()),
+    };
+    let etag: axum::headers::ETag = etag
+        .parse()
+        .map_err(|_| Error::InternalServerError("invalid icon hash".into()))?;
+    if let Some(axum::TypedHeader(if_none_match)) = if_none_match {
+        if !if_none_match.precondition_passes(&etag) {
+            return Ok((StatusCode::NOT_MODIFIED, axum::TypedHeader(etag)).into_response());
+        }
+    }
+
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE, content_type),
+            (axum::http::header::CACHE_CONTROL, "no-cache"),
+        ],
+        axum::TypedHeader(etag),
+        axum::body::StreamBody::new(tokio_util::io::ReaderStream::new(icon)),
+    )
+        .into_response())
+}
+
 async fn post_icon_handler(
     State(AppState {
-        pool, user_cache, ..
+        pool,
+        user_cache,
+        icon_store,
+        ..
     }): State<AppState>,
     jar: SignedCookieJar,
     axum::Json(req): axum::Json<PostIconRequest>,
@@ -1718,8 +4407,13 @@ async fn post_icon_handler(
         .ok_or(Error::SessionError)?;
     let user_id: i64 = sess.get(DEFAULT_USER_ID_KEY).ok_or(Error::SessionError)?;
 
+    let icon = tokio::task::spawn_blocking(move || icon_image::process(req.image))
+        .await
+        .map_err(|e| Error::InternalServerError(e.to_string()))??;
+
+    // icon_hash は保存する元画像の sha256。メタデータを取り除かない限りアップロードされたバイト列と同じ
     use sha2::digest::Digest as _;
-    let icon_hash = sha2::Sha256::digest(&req.image);
+    let icon_hash = format!("{:x}", sha2::Sha256::digest(&icon.original));
 
     let mut tx = pool.begin().await?;
 
@@ -1727,11 +4421,11 @@ async fn post_icon_handler(
         .bind(user_id)
         .execute(&mut *tx)
         .await?;
-    user_cache.invalidate(&user_id).await;
 
-    let rs = sqlx::query("INSERT INTO icons (user_id,icon_hash) VALUES (?,?)")
+    let rs = sqlx::query("INSERT INTO icons (user_id,icon_hash,format) VALUES (?,?,?)")
         .bind(user_id)
-        .bind(format!("{:x}", icon_hash))
+        .bind(&icon_hash)
+        .bind(icon.format.as_str())
         .execute(&mut *tx)
         .await?;
     let icon_id = rs.last_insert_id() as i64;
@@ -1742,10 +4436,37 @@ async fn post_icon_handler(
         .ok_or(Error::NotFound(
             "No user found for the userid in session".into(),
         ))?;
-    tx.commit().await?;
 
-    let mut file = File::create(format!("{ICON_BASE_PATH}/{0}.jpg", user_name)).unwrap();
-    file.write_all(&req.image).unwrap();
+    // 画像は一時ファイルに書き込んでおき、コミットしてから公開する。
+    // 書き込みに失敗した場合は icons の更新ごとロールバックし、一時ファイルは drop で消える
+    let mut staged = Vec::with_capacity(1 + icon.thumbnails.len());
+    staged.push(
+        icon_store
+            .stage(&user_name, &icon_hash, icon.format, None, &icon.original)
+            .await?,
+    );
+    for (size, thumbnail) in &icon.thumbnails {
+        staged.push(
+            icon_store
+                .stage(
+                    &user_name,
+                    &icon_hash,
+                    icon_image::IconFormat::Jpeg,
+                    Some(*size),
+                    thumbnail,
+                )
+                .await?,
+        );
+    }
+
+    tx.commit().await?;
+    // icons はコミット済みなので、公開に失敗してもエラーにはせずログに残す
+    for staged in staged {
+        if let Err(e) = staged.publish().await {
+            tracing::error!("failed to publish icon {}: {}", icon_hash, e);
+        }
+    }
+    user_cache.invalidate(&user_id).await;
 
     Ok((
         StatusCode::CREATED,
@@ -1772,7 +4493,7 @@ async fn get_me_handler(
 
     let user = user_cache
         .get_or_insert(&mut tx, user_id)
-        .await
+        .await?
         .ok_or(sqlx::Error::RowNotFound)?;
 
     tx.commit().await?;
@@ -1780,10 +4501,136 @@ async fn get_me_handler(
     Ok(axum::Json(user))
 }
 
+/// ユーザへの通知の種類。notifications.kind に保存する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum NotificationKind {
+    /// 配信予約のコラボレーターに指定された
+    CollaboratorAdded,
+    /// コラボレーターになっているライブ配信がキャンセルされた
+    LivestreamCanceled,
+    /// コラボレーターになっているライブ配信の日時が変更された
+    LivestreamRescheduled,
+}
+
+impl NotificationKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::CollaboratorAdded => "collaborator_added",
+            Self::LivestreamCanceled => "livestream_canceled",
+            Self::LivestreamRescheduled => "livestream_rescheduled",
+        }
+    }
+}
+
+impl TryFrom<String> for NotificationKind {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "collaborator_added" => Ok(Self::CollaboratorAdded),
+            "livestream_canceled" => Ok(Self::LivestreamCanceled),
+            "livestream_rescheduled" => Ok(Self::LivestreamRescheduled),
+            _ => Err(format!("unknown notification kind: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct NotificationModel {
+    id: i64,
+    #[allow(unused)]
+    user_id: i64,
+    #[sqlx(try_from = "String")]
+    kind: NotificationKind,
+    livestream_id: i64,
+    created_at: i64,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Notification {
+    id: i64,
+    kind: NotificationKind,
+    livestream_id: i64,
+    /// キャンセルされたライブ配信は null
+    livestream: Option<Livestream>,
+    created_at: i64,
+}
+
+// ログインユーザへの通知の一覧
+// GET /api/user/me/notifications
+async fn get_notifications_handler(
+    State(AppState {
+        pool,
+        user_cache,
+        tags_cache,
+        collaborators_cache,
+        livestream_cache,
+        ..
+    }): State<AppState>,
+    jar: SignedCookieJar,
+    axum::extract::OriginalUri(uri): axum::extract::OriginalUri,
+    Query(page): Query<pagination::PageQuery>,
+) -> Result<(axum::http::HeaderMap, axum::Json<Vec<Notification>>), Error> {
+    verify_user_session(&jar).await?;
+    let page = page.parse()?;
+
+    let cookie = jar.get(DEFAULT_SESSION_ID_KEY).ok_or(Error::SessionError)?;
+    let sess = CookieStore::new()
+        .load_session(cookie.value().to_owned())
+        .await?
+        .ok_or(Error::SessionError)?;
+    let user_id: i64 = sess.get(DEFAULT_USER_ID_KEY).ok_or(Error::SessionError)?;
+
+    let mut tx = pool.begin().await?;
+
+    let mut query_builder = QueryBuilder::new("SELECT * FROM notifications WHERE user_id = ");
+    query_builder.push_bind(user_id);
+    page.push_sql(&mut query_builder, Some("created_at"), "id");
+    let mut notification_models: Vec<NotificationModel> =
+        query_builder.build_query_as().fetch_all(&mut *tx).await?;
+    let next = page.finish(&mut notification_models, |m| pagination::Cursor {
+        created_at: m.created_at,
+        id: m.id,
+    });
+
+    let mut loader = ResponseLoader::new(
+        &user_cache,
+        &tags_cache,
+        &collaborators_cache,
+        &livestream_cache,
+    );
+    loader
+        .load_livestreams(&mut tx, notification_models.iter().map(|m| m.livestream_id))
+        .await?;
+    let notifications = notification_models
+        .into_iter()
+        .map(|m| Notification {
+            id: m.id,
+            kind: m.kind,
+            livestream_id: m.livestream_id,
+            livestream: loader.livestreams.get(&m.livestream_id).cloned(),
+            created_at: m.created_at,
+        })
+        .collect();
+
+    tx.commit().await?;
+
+    Ok((
+        pagination::link_headers(&uri, next),
+        axum::Json(notifications),
+    ))
+}
+
 // ユーザ登録API
 // POST /api/register
 async fn register_handler(
-    State(AppState { pool, .. }): State<AppState>,
+    State(AppState {
+        pool,
+        dns_registrar,
+        dns_names,
+        ..
+    }): State<AppState>,
     axum::Json(req): axum::Json<PostUserRequest>,
 ) -> Result<(StatusCode, axum::Json<User>), Error> {
     if req.name == "pipe" {
@@ -1807,31 +4654,46 @@ async fn register_handler(
     .await?;
     let user_id = result.last_insert_id() as i64;
 
-    let user = fill_user_response(
-        &mut tx,
-        UserModel {
-            id: user_id,
-            name: req.name,
-            display_name: Some(req.display_name),
-            description: Some(req.description),
-            hashed_password: Some(hashed_password),
-            dark_mode: req.theme.dark_mode,
-        },
-    )
-    .await?;
+    // 失敗した場合は tx を drop してユーザ登録ごとロールバックする
+    dns_registrar.add_record(&mut tx, &req.name).await?;
 
-    tx.commit().await?;
+    let name = req.name.clone();
+    let res = async {
+        let user = fill_user_response(
+            &mut tx,
+            UserModel {
+                id: user_id,
+                name: req.name,
+                display_name: Some(req.display_name),
+                description: Some(req.description),
+                hashed_password: Some(hashed_password),
+                dark_mode: req.theme.dark_mode,
+            },
+        )
+        .await?;
+        tx.commit().await?;
+        Ok::<_, Error>(user)
+    }
+    .await;
+    let user = match res {
+        Ok(user) => user,
+        Err(e) => {
+            // add_record 以降で失敗した場合は、トランザクション外に登録済みのレコードを取り消す
+            let removed = match pool.acquire().await {
+                Ok(mut conn) => dns_registrar.remove_record(&mut conn, &name).await,
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = removed {
+                tracing::warn!("failed to remove DNS record for {}: {}", name, e);
+            }
+            return Err(e);
+        }
+    };
+    dns_names.insert(&user.name);
 
     Ok((StatusCode::CREATED, axum::Json(user)))
 }
 
-#[derive(Debug, serde::Serialize)]
-struct Session {
-    id: String,
-    user_id: i64,
-    expires: i64,
-}
-
 // ユーザログインAPI
 // POST /api/login
 async fn login_handler(
@@ -1900,8 +4762,8 @@ async fn get_user_handler(
 
     let user = user_cache
         .get_or_insert(&mut tx, user_model.id)
-        .await
-        .unwrap();
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
 
     tx.commit().await?;
 
@@ -1939,6 +4801,22 @@ fn default_icon_hash() -> String {
         .to_string()
 }
 
+impl From<(UserModel, String)> for User {
+    fn from((user_model, icon_hash): (UserModel, String)) -> Self {
+        User {
+            id: user_model.id,
+            name: user_model.name,
+            display_name: user_model.display_name,
+            description: user_model.description,
+            theme: Theme {
+                id: user_model.id,
+                dark_mode: user_model.dark_mode,
+            },
+            icon_hash,
+        }
+    }
+}
+
 async fn fill_user_response(tx: &mut MySqlConnection, user_model: UserModel) -> sqlx::Result<User> {
     let icon_hash: String = sqlx::query_scalar("SELECT icon_hash FROM icons WHERE user_id = ?")
         .bind(user_model.id)
@@ -1946,17 +4824,41 @@ async fn fill_user_response(tx: &mut MySqlConnection, user_model: UserModel) ->
         .await?
         .unwrap_or(default_icon_hash());
 
-    Ok(User {
-        id: user_model.id,
-        name: user_model.name,
-        display_name: user_model.display_name,
-        description: user_model.description,
-        theme: Theme {
-            id: user_model.id,
-            dark_mode: user_model.dark_mode,
-        },
-        icon_hash,
-    })
+    Ok(User::from((user_model, icon_hash)))
+}
+
+async fn fill_user_responses(
+    tx: &mut MySqlConnection,
+    user_models: Vec<UserModel>,
+) -> sqlx::Result<Vec<User>> {
+    if user_models.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut query_builder =
+        QueryBuilder::new("SELECT user_id, icon_hash FROM icons WHERE user_id IN (");
+    let mut separated = query_builder.separated(", ");
+    for user_model in &user_models {
+        separated.push_bind(user_model.id);
+    }
+    separated.push_unseparated(")");
+    let icon_hashes: HashMap<i64, String> = query_builder
+        .build_query_as::<(i64, String)>()
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .collect();
+
+    Ok(user_models
+        .into_iter()
+        .map(|user_model| {
+            let icon_hash = icon_hashes
+                .get(&user_model.id)
+                .cloned()
+                .unwrap_or_else(default_icon_hash);
+            User::from((user_model, icon_hash))
+        })
+        .collect())
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -2074,7 +4976,7 @@ async fn get_user_statistics_handler(
 
     let livestreams: Vec<LivestreamModel> = user_id_to_livestreams_cache
         .get_or_insert(&mut tx, user.id)
-        .await;
+        .await?;
 
     // 合計視聴者数
     let mut viewers_count = 0;
@@ -2117,11 +5019,8 @@ async fn get_user_statistics_handler(
 
 async fn get_livestream_statistics_handler(
     State(AppState { pool, .. }): State<AppState>,
-    jar: SignedCookieJar,
-    Path((livestream_id,)): Path<(i64,)>,
+    LivestreamPath { livestream_id, .. }: LivestreamPath,
 ) -> Result<axum::Json<LivestreamStatistics>, Error> {
-    verify_user_session(&jar).await?;
-
     let mut tx = pool.begin().await?;
     let query = r#"
         WITH c AS (
@@ -2132,7 +5031,7 @@ async fn get_livestream_statistics_handler(
         ), tips AS (
             SELECT l.id AS id,IFNULL(SUM(l2.tip), 0) AS sum_tips
             FROM livestreams l
-            INNER JOIN livecomments l2 ON l.id = l2.livestream_id
+            INNER JOIN livecomments l2 ON l.id = l2.livestream_id AND l2.deleted_at IS NULL
             GROUP BY l.id
         )
         SELECT
@@ -2156,7 +5055,7 @@ async fn get_livestream_statistics_handler(
         .await?;
 
     // 最大チップ額
-    let MysqlDecimal(max_tip) = sqlx::query_scalar("SELECT IFNULL(MAX(tip), 0) FROM livestreams l INNER JOIN livecomments l2 ON l2.livestream_id = l.id WHERE l.id = ?")
+    let MysqlDecimal(max_tip) = sqlx::query_scalar("SELECT IFNULL(MAX(tip), 0) FROM livestreams l INNER JOIN livecomments l2 ON l2.livestream_id = l.id WHERE l.id = ? AND l2.deleted_at IS NULL")
         .bind(livestream_id)
         .fetch_one(&mut *tx)
         .await?;
@@ -2195,7 +5094,7 @@ async fn get_payment_result(
     let mut tx = pool.begin().await?;
 
     let MysqlDecimal(total_tip) =
-        sqlx::query_scalar("SELECT IFNULL(SUM(tip), 0) FROM livecomments")
+        sqlx::query_scalar("SELECT IFNULL(SUM(tip), 0) FROM livecomments WHERE deleted_at IS NULL")
             .fetch_one(&mut *tx)
             .await?;
 
@@ -2203,3 +5102,35 @@ async fn get_payment_result(
 
     Ok(axum::Json(PaymentResult { total_tip }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[track_caller]
+    fn assert_rejected(word: &str, mode: ngword::Mode) {
+        match compile_ng_word(word, mode) {
+            Err(Error::BadRequest(_)) => {}
+            Err(e) => panic!("{word:?} should be a bad request: {e:?}"),
+            Ok(_) => panic!("{word:?} should be rejected"),
+        }
+    }
+
+    #[test]
+    fn compile_ng_word_rejects_invalid_regexes() {
+        assert_rejected("(", ngword::Mode::Regex);
+        assert_rejected("[a-", ngword::Mode::Regex);
+        // REGEX_SIZE_LIMIT を超える
+        assert_rejected(r"\w{1000}{1000}", ngword::Mode::Regex);
+        compile_ng_word(r"\bspam\b", ngword::Mode::Regex).unwrap();
+    }
+
+    #[test]
+    fn compile_ng_word_rejects_words_matching_every_comment() {
+        assert_rejected("", ngword::Mode::Substring);
+        assert_rejected("%", ngword::Mode::Substring);
+        assert_rejected(" \u{3000}", ngword::Mode::Normalized);
+        assert_rejected("a*", ngword::Mode::Regex);
+        compile_ng_word("spam", ngword::Mode::Substring).unwrap();
+    }
+}
@@ -0,0 +1,319 @@
+//! Prometheus のテキスト形式で出力するためのメトリクス
+
+use sqlx::mysql::MySqlPool;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 秒単位のバケット境界
+const LATENCY_BUCKETS: [f64; 13] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+#[derive(Debug, Default)]
+pub struct Histogram {
+    /// LATENCY_BUCKETS の各区間 (累積ではない) と +Inf
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, d: Duration) {
+        let secs = d.as_secs_f64();
+        let i = LATENCY_BUCKETS
+            .iter()
+            .position(|&le| secs <= le)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(d.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// `labels` は `a="b",c="d"` の形式 (空でもよい)
+    fn encode(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let le = LATENCY_BUCKETS
+                .get(i)
+                .map(|le| le.to_string())
+                .unwrap_or_else(|| "+Inf".to_owned());
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels}{sep}le=\"{le}\"}} {cumulative}"
+            );
+        }
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{labels}}}")
+        };
+        let _ = writeln!(out, "{name}_sum{labels} {sum}");
+        let _ = writeln!(out, "{name}_count{labels} {cumulative}");
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// MySqlResultCache ごとのメトリクス
+#[derive(Debug)]
+pub struct CacheMetrics {
+    name: &'static str,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions_size: AtomicU64,
+    evictions_expired: AtomicU64,
+    load_latency: Histogram,
+}
+
+impl CacheMetrics {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions_size: AtomicU64::new(0),
+            evictions_expired: AtomicU64::new(0),
+            load_latency: Histogram::default(),
+        }
+    }
+
+    pub fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_load(&self, d: Duration) {
+        self.load_latency.observe(d);
+    }
+
+    pub fn record_eviction(&self, cause: moka::notification::RemovalCause) {
+        use moka::notification::RemovalCause;
+        match cause {
+            RemovalCause::Size => self.evictions_size.fetch_add(1, Ordering::Relaxed),
+            RemovalCause::Expired => self.evictions_expired.fetch_add(1, Ordering::Relaxed),
+            // 明示的な invalidate や上書きは追い出しとして数えない
+            RemovalCause::Explicit | RemovalCause::Replaced => return,
+        };
+    }
+}
+
+/// (メトリクス, 現在のエントリ数) の一覧を出力する
+pub fn encode_caches(out: &mut String, caches: &[(&CacheMetrics, u64)]) {
+    header(out, "isupipe_cache_hits_total", "counter", "Cache hits.");
+    for (m, _) in caches {
+        let hits = m.hits.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "isupipe_cache_hits_total{{cache=\"{}\"}} {hits}",
+            m.name
+        );
+    }
+    header(
+        out,
+        "isupipe_cache_misses_total",
+        "counter",
+        "Cache misses.",
+    );
+    for (m, _) in caches {
+        let misses = m.misses.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "isupipe_cache_misses_total{{cache=\"{}\"}} {misses}",
+            m.name
+        );
+    }
+    header(
+        out,
+        "isupipe_cache_evictions_total",
+        "counter",
+        "Entries evicted by capacity or TTL.",
+    );
+    for (m, _) in caches {
+        for (cause, n) in [
+            ("size", &m.evictions_size),
+            ("expired", &m.evictions_expired),
+        ] {
+            let _ = writeln!(
+                out,
+                "isupipe_cache_evictions_total{{cache=\"{}\",cause=\"{cause}\"}} {}",
+                m.name,
+                n.load(Ordering::Relaxed)
+            );
+        }
+    }
+    header(
+        out,
+        "isupipe_cache_entries",
+        "gauge",
+        "Current number of entries.",
+    );
+    for (m, entries) in caches {
+        let _ = writeln!(
+            out,
+            "isupipe_cache_entries{{cache=\"{}\"}} {entries}",
+            m.name
+        );
+    }
+    header(
+        out,
+        "isupipe_cache_load_duration_seconds",
+        "histogram",
+        "Time spent loading missing entries from MySQL.",
+    );
+    for (m, _) in caches {
+        m.load_latency.encode(
+            out,
+            "isupipe_cache_load_duration_seconds",
+            &format!("cache=\"{}\"", m.name),
+        );
+    }
+}
+
+#[derive(Debug, Default)]
+struct RouteMetrics {
+    /// status code to count
+    statuses: std::sync::Mutex<std::collections::BTreeMap<u16, u64>>,
+    latency: Histogram,
+}
+
+/// マッチしたルート (axum の MatchedPath) ごとのリクエスト数とレイテンシ
+#[derive(Debug, Default)]
+pub struct HttpMetrics {
+    /// (method, matched path) to metrics
+    routes: std::sync::RwLock<std::collections::BTreeMap<(String, String), Arc<RouteMetrics>>>,
+}
+
+impl HttpMetrics {
+    pub fn record(&self, method: &str, path: &str, status: u16, d: Duration) {
+        let key = (method.to_owned(), path.to_owned());
+        let route = self.routes.read().unwrap().get(&key).cloned();
+        let route = match route {
+            Some(route) => route,
+            None => self.routes.write().unwrap().entry(key).or_default().clone(),
+        };
+        *route.statuses.lock().unwrap().entry(status).or_default() += 1;
+        route.latency.observe(d);
+    }
+
+    pub fn encode(&self, out: &mut String) {
+        let routes = self.routes.read().unwrap();
+        header(
+            out,
+            "isupipe_http_requests_total",
+            "counter",
+            "HTTP requests by route and status code.",
+        );
+        for ((method, path), route) in routes.iter() {
+            for (status, n) in route.statuses.lock().unwrap().iter() {
+                let _ = writeln!(
+                    out,
+                    "isupipe_http_requests_total{{method=\"{method}\",route=\"{path}\",status=\"{status}\"}} {n}"
+                );
+            }
+        }
+        header(
+            out,
+            "isupipe_http_request_duration_seconds",
+            "histogram",
+            "HTTP request latency by route.",
+        );
+        for ((method, path), route) in routes.iter() {
+            route.latency.encode(
+                out,
+                "isupipe_http_request_duration_seconds",
+                &format!("method=\"{method}\",route=\"{path}\""),
+            );
+        }
+    }
+}
+
+/// ルーティング後に適用する (MatchedPath を取り出すため route_layer で使う)
+pub async fn track_http<B>(
+    axum::extract::State(metrics): axum::extract::State<Arc<HttpMetrics>>,
+    matched_path: axum::extract::MatchedPath,
+    req: axum::http::Request<B>,
+    next: axum::middleware::Next<B>,
+) -> axum::response::Response {
+    let method = req.method().clone();
+    let start = std::time::Instant::now();
+    let res = next.run(req).await;
+    metrics.record(
+        method.as_str(),
+        matched_path.as_str(),
+        res.status().as_u16(),
+        start.elapsed(),
+    );
+    res
+}
+
+/// コネクション取得の待ち時間を計測する MySqlPool
+///
+/// 計測漏れがないように MySqlPool そのものは公開しない。
+/// Executor として渡す場合は `&mut *pool.acquire().await?` とする。
+#[derive(Debug, Clone)]
+pub struct MeteredPool {
+    pool: MySqlPool,
+    acquire_latency: Arc<Histogram>,
+}
+
+impl MeteredPool {
+    pub fn new(pool: MySqlPool) -> Self {
+        Self {
+            pool,
+            acquire_latency: Arc::default(),
+        }
+    }
+
+    pub async fn acquire(&self) -> sqlx::Result<sqlx::pool::PoolConnection<sqlx::MySql>> {
+        let start = std::time::Instant::now();
+        let conn = self.pool.acquire().await;
+        self.acquire_latency.observe(start.elapsed());
+        conn
+    }
+
+    pub async fn begin(&self) -> sqlx::Result<sqlx::Transaction<'static, sqlx::MySql>> {
+        let start = std::time::Instant::now();
+        let tx = self.pool.begin().await;
+        self.acquire_latency.observe(start.elapsed());
+        tx
+    }
+
+    pub fn encode(&self, out: &mut String) {
+        header(
+            out,
+            "isupipe_db_pool_connections",
+            "gauge",
+            "Connections currently held by the pool.",
+        );
+        let _ = writeln!(out, "isupipe_db_pool_connections {}", self.pool.size());
+        header(
+            out,
+            "isupipe_db_pool_idle_connections",
+            "gauge",
+            "Idle connections in the pool.",
+        );
+        let _ = writeln!(
+            out,
+            "isupipe_db_pool_idle_connections {}",
+            self.pool.num_idle()
+        );
+        header(
+            out,
+            "isupipe_db_pool_acquire_duration_seconds",
+            "histogram",
+            "Time spent waiting for a connection (and starting a transaction for begin).",
+        );
+        self.acquire_latency
+            .encode(out, "isupipe_db_pool_acquire_duration_seconds", "");
+    }
+}
@@ -0,0 +1,460 @@
+//! NG ワードによるスパム判定。
+//!
+//! NG ワードごとに照合方法 ([`Mode`]) を持つ。`substring` は
+//! `comment LIKE CONCAT('%', word, '%')` (utf8mb4_bin) と同じ判定をメモリ上で行う。
+//! ワイルドカードを含まない NG ワードは 1 つの Aho-Corasick オートマトンにまとめて一度に走査し、
+//! `%` や `_` を含むものだけ個別に照合する。
+
+use aho_corasick::AhoCorasick;
+use regex::RegexSet;
+use unicode_normalization::UnicodeNormalization as _;
+
+/// 正規表現をコンパイルした際の大きさの上限
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// NG ワードの照合方法。ng_words.mode に保存する
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// 部分一致。`%` と `_` は LIKE のワイルドカードとして扱う
+    #[default]
+    Substring,
+    /// 前後が英数字でない位置での完全一致 (ワイルドカードなし)
+    Word,
+    /// 正規表現 (regex crate の構文)
+    Regex,
+    /// NFKC 正規化、大文字小文字とひらがなカタカナの同一視、空白の除去をした上での部分一致
+    Normalized,
+}
+
+impl Mode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Substring => "substring",
+            Self::Word => "word",
+            Self::Regex => "regex",
+            Self::Normalized => "normalized",
+        }
+    }
+}
+
+impl TryFrom<String> for Mode {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "substring" => Ok(Self::Substring),
+            "word" => Ok(Self::Word),
+            "regex" => Ok(Self::Regex),
+            "normalized" => Ok(Self::Normalized),
+            _ => Err(format!("unknown ng word mode: {s}")),
+        }
+    }
+}
+
+/// Normalized モードで NG ワードとコメントの両方に適用する
+pub fn normalize(text: &str) -> String {
+    text.nfkc()
+        .flat_map(char::to_lowercase)
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c {
+            // カタカナをひらがなに寄せる (ァ..ヶ, ヽ, ヾ)
+            '\u{30A1}'..='\u{30F6}' | '\u{30FD}' | '\u{30FE}' => {
+                char::from_u32(c as u32 - 0x60).unwrap_or(c)
+            }
+            c => c,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Char(char),
+    /// `_`: 任意の 1 文字
+    AnyChar,
+    /// `%`: 任意の 0 文字以上
+    AnyString,
+}
+
+/// MySQL の LIKE パターン (エスケープ文字は `\`) を解釈する
+fn parse_like(pattern: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            // 末尾の `\` はそれ自体を表す
+            '\\' => Token::Char(chars.next().unwrap_or('\\')),
+            '_' => Token::AnyChar,
+            '%' => {
+                if tokens.last() == Some(&Token::AnyString) {
+                    continue;
+                }
+                Token::AnyString
+            }
+            c => Token::Char(c),
+        };
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// `%` で区切られた各区間。区間内は固定長なので左から貪欲に照合してよい
+#[derive(Debug, Clone)]
+struct LikePattern {
+    /// 先頭が `%` でなければ先頭の区間はテキストの先頭に一致する必要がある
+    anchored_start: bool,
+    /// 末尾が `%` でなければ最後の区間はテキストの末尾に一致する必要がある
+    anchored_end: bool,
+    /// None は `_`
+    segments: Vec<Vec<Option<char>>>,
+}
+
+impl LikePattern {
+    fn new(tokens: &[Token]) -> Self {
+        let mut segments = vec![Vec::new()];
+        for token in tokens {
+            match token {
+                Token::Char(c) => segments.last_mut().unwrap().push(Some(*c)),
+                Token::AnyChar => segments.last_mut().unwrap().push(None),
+                Token::AnyString => segments.push(Vec::new()),
+            }
+        }
+        Self {
+            anchored_start: tokens.first() != Some(&Token::AnyString),
+            anchored_end: tokens.last() != Some(&Token::AnyString),
+            segments,
+        }
+    }
+
+    fn is_match(&self, text: &[char]) -> bool {
+        fn matches_at(segment: &[Option<char>], text: &[char], pos: usize) -> bool {
+            text.get(pos..pos + segment.len()).is_some_and(|window| {
+                segment
+                    .iter()
+                    .zip(window)
+                    .all(|(p, c)| p.map_or(true, |p| p == *c))
+            })
+        }
+
+        let segments = &self.segments;
+        // `%` を含まないパターンは全体一致
+        if segments.len() == 1 {
+            return segments[0].len() == text.len() && matches_at(&segments[0], text, 0);
+        }
+
+        let (first, rest) = segments.split_first().unwrap();
+        let (last, middle) = rest.split_last().unwrap();
+        let mut start = 0;
+        let mut end = text.len();
+        if self.anchored_start {
+            if !matches_at(first, text, 0) {
+                return false;
+            }
+            start = first.len();
+        }
+        if self.anchored_end {
+            let Some(last_start) = end.checked_sub(last.len()) else {
+                return false;
+            };
+            if last_start < start || !matches_at(last, text, last_start) {
+                return false;
+            }
+            end = last_start;
+        }
+        // 中間の区間は [start, end) の中に順番に現れればよい
+        let unanchored = (!self.anchored_start)
+            .then_some(first)
+            .into_iter()
+            .chain(middle)
+            .chain((!self.anchored_end).then_some(last));
+        for segment in unanchored {
+            loop {
+                if start + segment.len() > end {
+                    return false;
+                }
+                if matches_at(segment, &text[..end], start) {
+                    start += segment.len();
+                    break;
+                }
+                start += 1;
+            }
+        }
+        true
+    }
+}
+
+/// ライブ配信に登録された NG ワードをまとめてコンパイルしたもの
+#[derive(Debug, Clone)]
+pub struct NgWordMatcher {
+    /// ワイルドカードを含まない substring の NG ワード
+    literals: Option<AhoCorasick>,
+    /// ワイルドカードを含む substring の NG ワード
+    patterns: Vec<LikePattern>,
+    words: Option<AhoCorasick>,
+    regexes: Option<RegexSet>,
+    /// normalize 済みの NG ワード
+    normalized: Option<AhoCorasick>,
+}
+
+impl NgWordMatcher {
+    /// 正規表現として不正な NG ワードがあればエラー
+    pub fn new<'a>(rules: impl IntoIterator<Item = (&'a str, Mode)>) -> Result<Self, String> {
+        let mut literals = Vec::new();
+        let mut patterns = Vec::new();
+        let mut words = Vec::new();
+        let mut regexes = Vec::new();
+        let mut normalized = Vec::new();
+        for (word, mode) in rules {
+            match mode {
+                Mode::Substring => {
+                    let tokens = parse_like(&format!("%{word}%"));
+                    // `%` + 文字列 + `%` の形なら単純な部分文字列検索でよい
+                    let inner = match tokens.as_slice() {
+                        [Token::AnyString, inner @ .., Token::AnyString] => inner,
+                        _ => &[][..],
+                    };
+                    if !inner.is_empty() && inner.iter().all(|t| matches!(t, Token::Char(_))) {
+                        literals.push(
+                            inner
+                                .iter()
+                                .map(|t| match t {
+                                    Token::Char(c) => *c,
+                                    _ => unreachable!(),
+                                })
+                                .collect::<String>(),
+                        );
+                    } else {
+                        patterns.push(LikePattern::new(&tokens));
+                    }
+                }
+                Mode::Word => words.push(word.to_owned()),
+                Mode::Regex => regexes.push(word.to_owned()),
+                Mode::Normalized => normalized.push(normalize(word)),
+            }
+        }
+
+        fn automaton(patterns: Vec<String>) -> Option<AhoCorasick> {
+            (!patterns.is_empty())
+                .then(|| AhoCorasick::new(patterns).expect("failed to build NG word automaton"))
+        }
+        let regexes = if regexes.is_empty() {
+            None
+        } else {
+            Some(
+                regex::RegexSetBuilder::new(&regexes)
+                    .size_limit(REGEX_SIZE_LIMIT)
+                    .build()
+                    .map_err(|e| e.to_string())?,
+            )
+        };
+        Ok(Self {
+            literals: automaton(literals),
+            patterns,
+            words: automaton(words),
+            regexes,
+            normalized: automaton(normalized),
+        })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        if self.literals.as_ref().is_some_and(|ac| ac.is_match(text)) {
+            return true;
+        }
+        if let Some(words) = &self.words {
+            let is_boundary = |c: Option<char>| c.map_or(true, |c| !c.is_alphanumeric());
+            let bounded = words.find_overlapping_iter(text).any(|m| {
+                is_boundary(text[..m.start()].chars().next_back())
+                    && is_boundary(text[m.end()..].chars().next())
+            });
+            if bounded {
+                return true;
+            }
+        }
+        if self.regexes.as_ref().is_some_and(|set| set.is_match(text)) {
+            return true;
+        }
+        if self
+            .normalized
+            .as_ref()
+            .is_some_and(|ac| ac.is_match(&normalize(text)))
+        {
+            return true;
+        }
+        if self.patterns.is_empty() {
+            return false;
+        }
+        let chars: Vec<char> = text.chars().collect();
+        self.patterns.iter().any(|p| p.is_match(&chars))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `comment LIKE CONCAT('%', word, '%')` の結果が expected であることを、
+    /// NgWordMatcher (ワイルドカードがなければ Aho-Corasick) と LikePattern の両方で確かめる
+    #[track_caller]
+    fn assert_substring(word: &str, text: &str, expected: bool) {
+        let matcher = NgWordMatcher::new([(word, Mode::Substring)]).unwrap();
+        assert_eq!(
+            matcher.is_match(text),
+            expected,
+            "NgWordMatcher: {word:?} in {text:?}"
+        );
+        let pattern = LikePattern::new(&parse_like(&format!("%{word}%")));
+        let chars: Vec<char> = text.chars().collect();
+        assert_eq!(
+            pattern.is_match(&chars),
+            expected,
+            "LikePattern: {word:?} in {text:?}"
+        );
+    }
+
+    #[track_caller]
+    fn assert_like(pattern: &str, text: &str, expected: bool) {
+        let chars: Vec<char> = text.chars().collect();
+        assert_eq!(
+            LikePattern::new(&parse_like(pattern)).is_match(&chars),
+            expected,
+            "{text:?} LIKE {pattern:?}"
+        );
+    }
+
+    #[test]
+    fn literal_words() {
+        assert_substring("spam", "this is spam!", true);
+        assert_substring("spam", "this is Spam!", false);
+        assert_substring("spam", "spa m", false);
+        assert_substring("スパム", "これはスパムです", true);
+    }
+
+    #[test]
+    fn percent_matches_any_string() {
+        assert_substring("a%c", "xxabbbcxx", true);
+        assert_substring("a%c", "ac", true);
+        assert_substring("a%c", "ca", false);
+        // `%%` は `%` と同じ
+        assert_substring("a%%c", "abc", true);
+        assert_eq!(parse_like("a%%%c"), parse_like("a%c"));
+    }
+
+    #[test]
+    fn underscore_matches_one_character() {
+        assert_substring("a_c", "abc", true);
+        assert_substring("a_c", "ac", false);
+        assert_substring("a_c", "abbc", false);
+        // utf8mb4 では `_` はバイトではなく 1 文字に一致する
+        assert_substring("ア_ウ", "イアイウエ", true);
+        assert_substring("ア_ウ", "アウ", false);
+        assert_substring("ア__", "ア", false);
+    }
+
+    #[test]
+    fn escaped_wildcards_are_literals() {
+        assert_substring("100\\%", "100% off", true);
+        assert_substring("100\\%", "1000 off", false);
+        assert_substring("a\\_c", "a_c", true);
+        assert_substring("a\\_c", "abc", false);
+        assert_substring("a\\\\b", "a\\b", true);
+        assert_substring("a\\\\b", "ab", false);
+    }
+
+    #[test]
+    fn trailing_backslash_escapes_the_wrapping_percent() {
+        // CONCAT('%', 'abc\\', '%') は `%abc\%` になり、末尾の `%` は文字として扱われる
+        assert_substring("abc\\", "xabc%", true);
+        assert_substring("abc\\", "xabc%y", false);
+        assert_substring("abc\\", "xabc\\", false);
+    }
+
+    #[test]
+    fn empty_word_matches_everything() {
+        assert_substring("", "", true);
+        assert_substring("", "anything", true);
+        assert_substring("%", "", true);
+    }
+
+    #[test]
+    fn anchored_segments() {
+        assert_like("abc", "abc", true);
+        assert_like("abc", "abcd", false);
+        assert_like("a%", "abc", true);
+        assert_like("a%", "ba", false);
+        assert_like("%c", "abc", true);
+        assert_like("%c", "cb", false);
+        assert_like("a%c", "abc", true);
+        assert_like("a%c", "ac", true);
+        assert_like("a%c", "a", false);
+        // 先頭と末尾の区間は重ならない
+        assert_like("ab%bc", "abc", false);
+        assert_like("ab%bc", "abbc", true);
+        assert_like("a_", "ab", true);
+        assert_like("a_", "abc", false);
+    }
+
+    #[test]
+    fn unanchored_segments_match_in_order() {
+        assert_like("%a_c%", "abac", false);
+        assert_like("%a_c%", "xabcx", true);
+        assert_like("%ab%ba%", "aba", false);
+        assert_like("%ab%ba%", "abba", true);
+        assert_like("%b%a%", "ab", false);
+        assert_like("x%ab%c", "xaabc", true);
+    }
+
+    #[test]
+    fn normalize_folds_width_case_kana_and_whitespace() {
+        assert_eq!(normalize("ＡＢＣ１２３"), "abc123");
+        assert_eq!(normalize("SpAm"), "spam");
+        assert_eq!(normalize("スパム"), "すぱむ");
+        // 半角カタカナは NFKC で全角になってからひらがなに寄せる
+        assert_eq!(normalize("ｽﾊﾟﾑ"), "すぱむ");
+        assert_eq!(normalize("ヽヾ"), "ゝゞ");
+        assert_eq!(normalize(" s p\u{3000}a\tm\n"), "spam");
+    }
+
+    #[test]
+    fn normalized_words_match_folded_text() {
+        let matcher = NgWordMatcher::new([("スパム", Mode::Normalized)]).unwrap();
+        assert!(matcher.is_match("これは す ぱ む です"));
+        assert!(matcher.is_match("ｽﾊﾟﾑ"));
+        assert!(!matcher.is_match("すぱ"));
+
+        let matcher = NgWordMatcher::new([("ＳＰＡＭ", Mode::Normalized)]).unwrap();
+        assert!(matcher.is_match("Sp Am"));
+    }
+
+    #[test]
+    fn words_need_boundaries_at_both_ends() {
+        let matcher = NgWordMatcher::new([("spam", Mode::Word)]).unwrap();
+        assert!(matcher.is_match("spam"));
+        assert!(matcher.is_match("no spam!"));
+        assert!(!matcher.is_match("spammer"));
+        assert!(!matcher.is_match("antispam"));
+        assert!(!matcher.is_match("スパムspamスパム"));
+        assert!(!matcher.is_match("spam1"));
+    }
+
+    #[test]
+    fn words_check_every_overlapping_match() {
+        // 先に見つかる "ab" は境界がないが、重なる "abc" は境界がある
+        let matcher = NgWordMatcher::new([("ab", Mode::Word), ("abc", Mode::Word)]).unwrap();
+        assert!(matcher.is_match("abc"));
+        assert!(!matcher.is_match("abcd"));
+
+        let matcher = NgWordMatcher::new([("aa", Mode::Word)]).unwrap();
+        assert!(!matcher.is_match("aaa"));
+        assert!(matcher.is_match("aaa aa"));
+    }
+
+    #[test]
+    fn regexes() {
+        let matcher = NgWordMatcher::new([(r"^\d{3}-\d{4}$", Mode::Regex)]).unwrap();
+        assert!(matcher.is_match("123-4567"));
+        assert!(!matcher.is_match("call 123-4567"));
+
+        assert!(NgWordMatcher::new([("(", Mode::Regex)]).is_err());
+        assert!(NgWordMatcher::new([(r"\w{1000}{1000}", Mode::Regex)]).is_err());
+    }
+}
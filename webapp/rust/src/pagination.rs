@@ -0,0 +1,363 @@
+//! 一覧 API のカーソルによるページング
+//!
+//! 一覧は (created_at, id) の降順に並べる。created_at を持たないもの (ライブ配信) は id のみで並べ、
+//! カーソルの created_at は 0 とする。ライブ配信の検索では created_at の代わりに並び順のキーを使う。
+//! 次のページがある場合は `Link: <...>; rel="next"` ヘッダで次のページの URL を返す。
+
+use crate::Error;
+use base64::Engine as _;
+use sqlx::{MySql, QueryBuilder};
+
+/// limit に指定できる最大値
+pub const MAX_LIMIT: i64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cursor {
+    pub created_at: i64,
+    pub id: i64,
+}
+
+impl Cursor {
+    /// クライアントからは中身を解釈しない不透明な文字列として扱ってもらう
+    fn encode(self) -> String {
+        base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(format!("{}:{}", self.created_at, self.id))
+    }
+
+    fn decode(s: &str) -> Result<Self, Error> {
+        let invalid = || Error::BadRequest("invalid cursor".into());
+        let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(s)
+            .map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let (created_at, id) = decoded.split_once(':').ok_or_else(invalid)?;
+        Ok(Self {
+            created_at: created_at.parse().map_err(|_| invalid())?,
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// 一覧 API の共通のクエリパラメータ。各ハンドラで他のクエリとは別の `Query<PageQuery>` として受け取る
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct PageQuery {
+    #[serde(default)]
+    limit: String,
+    /// このカーソルより古いものを返す
+    before: Option<String>,
+    /// このカーソルより新しいものを返す
+    after: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    /// 最新のものから
+    First,
+    Before(Cursor),
+    After(Cursor),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Page {
+    /// None なら全件
+    limit: Option<i64>,
+    position: Position,
+}
+
+impl PageQuery {
+    pub fn parse(&self) -> Result<Page, Error> {
+        let limit = if self.limit.is_empty() {
+            None
+        } else {
+            let limit: i64 = self
+                .limit
+                .parse()
+                .map_err(|_| Error::BadRequest("failed to parse limit".into()))?;
+            if !(0..=MAX_LIMIT).contains(&limit) {
+                return Err(Error::BadRequest(
+                    format!("limit must be between 0 and {}", MAX_LIMIT).into(),
+                ));
+            }
+            Some(limit)
+        };
+        let position = match (&self.before, &self.after) {
+            (None, None) => Position::First,
+            (Some(before), None) => Position::Before(Cursor::decode(before)?),
+            (None, Some(after)) => Position::After(Cursor::decode(after)?),
+            (Some(_), Some(_)) => {
+                return Err(Error::BadRequest(
+                    "before and after cannot be specified together".into(),
+                ))
+            }
+        };
+        Ok(Page { limit, position })
+    }
+}
+
+impl Page {
+    /// WHERE 句の後ろにカーソルの条件と ORDER BY、LIMIT を追加する。
+    /// `created_at` が None なら `id` のみで並べる。
+    ///
+    /// 取得した結果は [`Page::finish`] に渡すこと
+    pub fn push_sql(&self, qb: &mut QueryBuilder<'_, MySql>, created_at: Option<&str>, id: &str) {
+        let (cursor, op, order) = match self.position {
+            Position::First => (None, "", "DESC"),
+            Position::Before(cursor) => (Some(cursor), "<", "DESC"),
+            // カーソルの直後から数えるために昇順で取得する
+            Position::After(cursor) => (Some(cursor), ">", "ASC"),
+        };
+        if let Some(cursor) = cursor {
+            match created_at {
+                Some(created_at) => {
+                    qb.push(format!(" AND ({created_at} {op} "));
+                    qb.push_bind(cursor.created_at);
+                    qb.push(format!(" OR ({created_at} = "));
+                    qb.push_bind(cursor.created_at);
+                    qb.push(format!(" AND {id} {op} "));
+                    qb.push_bind(cursor.id);
+                    qb.push("))");
+                }
+                None => {
+                    qb.push(format!(" AND {id} {op} "));
+                    qb.push_bind(cursor.id);
+                }
+            }
+        }
+        match created_at {
+            Some(created_at) => qb.push(format!(" ORDER BY {created_at} {order}, {id} {order}")),
+            None => qb.push(format!(" ORDER BY {id} {order}")),
+        };
+        if let Some(limit) = self.limit {
+            qb.push(" LIMIT ");
+            qb.push_bind(limit);
+        }
+    }
+
+    /// push_sql で取得した結果を降順に揃え、次のページの位置を返す
+    pub fn finish<T>(&self, items: &mut [T], key: impl Fn(&T) -> Cursor) -> Option<Position> {
+        if matches!(self.position, Position::After(_)) {
+            items.reverse();
+        }
+        self.next(items, key)
+    }
+
+    /// メモリ上の一覧を降順に並べてページングする
+    pub fn apply<T>(
+        &self,
+        mut items: Vec<T>,
+        key: impl Fn(&T) -> Cursor,
+    ) -> (Vec<T>, Option<Position>) {
+        items.sort_by_key(|item| std::cmp::Reverse(key(item)));
+        let limit = self.limit.map_or(usize::MAX, |limit| limit as usize);
+        let mut items: Vec<T> = match self.position {
+            Position::First => items.into_iter().take(limit).collect(),
+            Position::Before(cursor) => items
+                .into_iter()
+                .filter(|item| key(item) < cursor)
+                .take(limit)
+                .collect(),
+            Position::After(cursor) => {
+                // カーソルに近い (古い) 方から limit 件
+                let mut newer: Vec<T> = items
+                    .into_iter()
+                    .filter(|item| key(item) > cursor)
+                    .collect();
+                let skip = newer.len().saturating_sub(limit);
+                newer.drain(..skip);
+                newer
+            }
+        };
+        let next = self.next(&items, key);
+        items.shrink_to_fit();
+        (items, next)
+    }
+
+    /// limit 件ちょうど返した場合は続きがあるものとみなす
+    fn next<T>(&self, items: &[T], key: impl Fn(&T) -> Cursor) -> Option<Position> {
+        let limit = self.limit?;
+        if limit == 0 || (items.len() as i64) < limit {
+            return None;
+        }
+        match self.position {
+            Position::First | Position::Before(_) => {
+                items.last().map(|item| Position::Before(key(item)))
+            }
+            Position::After(_) => items.first().map(|item| Position::After(key(item))),
+        }
+    }
+}
+
+/// 次のページがあれば `Link` ヘッダを返す。before / after 以外のクエリパラメータは引き継ぐ
+pub fn link_headers(uri: &axum::http::Uri, next: Option<Position>) -> axum::http::HeaderMap {
+    let mut headers = axum::http::HeaderMap::new();
+    let (name, cursor) = match next {
+        Some(Position::Before(cursor)) => ("before", cursor),
+        Some(Position::After(cursor)) => ("after", cursor),
+        Some(Position::First) | None => return headers,
+    };
+    let mut query: Vec<&str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split('=').next().unwrap_or_default();
+            !pair.is_empty() && key != "before" && key != "after"
+        })
+        .collect();
+    let cursor = format!("{}={}", name, cursor.encode());
+    query.push(&cursor);
+    let link = format!("<{}?{}>; rel=\"next\"", uri.path(), query.join("&"));
+    if let Ok(value) = axum::http::HeaderValue::from_str(&link) {
+        headers.insert(axum::http::header::LINK, value);
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(limit: &str, before: Option<Cursor>, after: Option<Cursor>) -> Page {
+        PageQuery {
+            limit: limit.to_owned(),
+            before: before.map(Cursor::encode),
+            after: after.map(Cursor::encode),
+        }
+        .parse()
+        .unwrap()
+    }
+
+    fn cursor(id: i64) -> Cursor {
+        Cursor {
+            created_at: id * 10,
+            id,
+        }
+    }
+
+    fn ids(items: &[Cursor]) -> Vec<i64> {
+        items.iter().map(|c| c.id).collect()
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        for c in [
+            Cursor {
+                created_at: 0,
+                id: 0,
+            },
+            Cursor {
+                created_at: 1_700_000_000,
+                id: 42,
+            },
+            Cursor {
+                created_at: -1,
+                id: i64::MAX,
+            },
+        ] {
+            assert_eq!(Cursor::decode(&c.encode()).unwrap(), c);
+        }
+    }
+
+    #[test]
+    fn cursor_rejects_malformed_input() {
+        let encode = |s: &str| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(s);
+        for s in [
+            "not base64!".to_owned(),
+            "MTox=".to_owned(),
+            encode("12"),
+            encode("12:"),
+            encode("a:1"),
+            encode("1:2:3"),
+        ] {
+            assert!(
+                matches!(Cursor::decode(&s), Err(Error::BadRequest(_))),
+                "{s:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_rejects_bad_limits_and_both_cursors() {
+        let query = |limit: &str| PageQuery {
+            limit: limit.to_owned(),
+            ..Default::default()
+        };
+        assert!(query("-1").parse().is_err());
+        assert!(query(&(MAX_LIMIT + 1).to_string()).parse().is_err());
+        assert!(query("x").parse().is_err());
+        assert!(PageQuery {
+            before: Some(cursor(1).encode()),
+            after: Some(cursor(2).encode()),
+            ..Default::default()
+        }
+        .parse()
+        .is_err());
+    }
+
+    #[test]
+    fn apply_pages_newest_first() {
+        let items: Vec<Cursor> = [3, 1, 5, 2, 4].into_iter().map(cursor).collect();
+
+        let (first, next) = page("2", None, None).apply(items.clone(), |c| *c);
+        assert_eq!(ids(&first), [5, 4]);
+        assert_eq!(next, Some(Position::Before(cursor(4))));
+
+        let (second, next) = page("2", Some(cursor(4)), None).apply(items.clone(), |c| *c);
+        assert_eq!(ids(&second), [3, 2]);
+        assert_eq!(next, Some(Position::Before(cursor(2))));
+
+        let (last, next) = page("2", Some(cursor(2)), None).apply(items.clone(), |c| *c);
+        assert_eq!(ids(&last), [1]);
+        assert_eq!(next, None);
+
+        let (all, next) = page("", None, None).apply(items, |c| *c);
+        assert_eq!(ids(&all), [5, 4, 3, 2, 1]);
+        assert_eq!(next, None);
+    }
+
+    #[test]
+    fn apply_after_returns_the_items_next_to_the_cursor() {
+        let items: Vec<Cursor> = (1..=5).map(cursor).collect();
+
+        let (newer, next) = page("2", None, Some(cursor(1))).apply(items.clone(), |c| *c);
+        assert_eq!(ids(&newer), [3, 2]);
+        assert_eq!(next, Some(Position::After(cursor(3))));
+
+        let (newest, next) = page("2", None, Some(cursor(4))).apply(items, |c| *c);
+        assert_eq!(ids(&newest), [5]);
+        assert_eq!(next, None);
+    }
+
+    #[test]
+    fn finish_restores_descending_order_and_links_the_next_page() {
+        let uri: axum::http::Uri = "/api/livestream/1/livecomment?limit=2&before=old&expand=user"
+            .parse()
+            .unwrap();
+
+        // push_sql の結果は before なら降順、after なら昇順
+        let page_before = page("2", Some(cursor(9)), None);
+        let mut rows = vec![cursor(8), cursor(7)];
+        let next = page_before.finish(&mut rows, |c| *c);
+        assert_eq!(ids(&rows), [8, 7]);
+        let headers = link_headers(&uri, next);
+        assert_eq!(
+            headers[axum::http::header::LINK],
+            format!(
+                "</api/livestream/1/livecomment?limit=2&expand=user&before={}>; rel=\"next\"",
+                cursor(7).encode()
+            )
+        );
+
+        let page_after = page("2", None, Some(cursor(1)));
+        let mut rows = vec![cursor(2), cursor(3)];
+        let next = page_after.finish(&mut rows, |c| *c);
+        assert_eq!(ids(&rows), [3, 2]);
+        assert_eq!(next, Some(Position::After(cursor(3))));
+
+        // limit に満たなければ次のページはない
+        let mut rows = vec![cursor(8)];
+        let next = page_before.finish(&mut rows, |c| *c);
+        assert_eq!(next, None);
+        assert!(link_headers(&uri, next).is_empty());
+    }
+}
@@ -0,0 +1,561 @@
+//! 予約枠 (reservation_slots) の残数をメモリ上で管理する台帳
+//!
+//! 予約できるかどうかは MySQL の行ロックではなくこの台帳だけで判定し、確保した結果は
+//! 予約のトランザクションの中で reservation_slots にも書き込む。
+//! 台帳は起動時と /api/initialize の後に reservation_slots から作り直す。
+//! このプロセスだけが reservation_slots を更新することを前提とする。
+
+use crate::Error;
+use chrono::{DateTime, NaiveDate, TimeZone as _, Utc};
+use sqlx::mysql::MySqlConnection;
+use sqlx::Connection as _;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// 予約枠を一度に INSERT する行数
+const INSERT_BATCH_SIZE: usize = 1000;
+
+/// 予約できる期間と予約枠の設定
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    pub term_start_at: i64,
+    pub term_end_at: i64,
+    /// 予約枠の長さ (秒)。予約の開始、終了時刻は term_start_at からこの倍数だけ離れている必要がある。
+    /// reservation_slots の各行もこの長さで区切られている必要がある
+    pub slot_seconds: i64,
+    /// 予約枠の定員。読み直すたびに、予約済みの数を保ったまま全ての予約枠に適用する
+    pub slot_capacity: i64,
+}
+
+impl Default for Config {
+    /// 2023/11/25 10:00 (JST) からの１年間、1 時間ごと、定員 5
+    fn default() -> Self {
+        let at = |year, month, day| {
+            Utc.from_utc_datetime(
+                &NaiveDate::from_ymd_opt(year, month, day)
+                    .unwrap()
+                    .and_hms_opt(1, 0, 0)
+                    .unwrap(),
+            )
+            .timestamp()
+        };
+        Self {
+            term_start_at: at(2023, 11, 25),
+            term_end_at: at(2024, 11, 25),
+            slot_seconds: 60 * 60,
+            slot_capacity: 5,
+        }
+    }
+}
+
+impl Config {
+    /// `ISUCON13_RESERVATION_TERM_START`, `ISUCON13_RESERVATION_TERM_END` (RFC 3339),
+    /// `ISUCON13_RESERVATION_SLOT_SECONDS`, `ISUCON13_RESERVATION_SLOT_CAPACITY` で上書きする
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let time = |key: &str, default: i64| {
+            std::env::var(key)
+                .ok()
+                .map(|v| {
+                    DateTime::parse_from_rfc3339(&v)
+                        .unwrap_or_else(|e| panic!("{key} must be an RFC 3339 date-time: {e}"))
+                        .timestamp()
+                })
+                .unwrap_or(default)
+        };
+        let int = |key: &str, default: i64| {
+            std::env::var(key)
+                .ok()
+                .map(|v| {
+                    v.parse()
+                        .unwrap_or_else(|e| panic!("{key} must be an integer: {e}"))
+                })
+                .unwrap_or(default)
+        };
+        let config = Self {
+            term_start_at: time("ISUCON13_RESERVATION_TERM_START", default.term_start_at),
+            term_end_at: time("ISUCON13_RESERVATION_TERM_END", default.term_end_at),
+            slot_seconds: int("ISUCON13_RESERVATION_SLOT_SECONDS", default.slot_seconds),
+            slot_capacity: int("ISUCON13_RESERVATION_SLOT_CAPACITY", default.slot_capacity),
+        };
+        assert!(
+            config.term_start_at < config.term_end_at,
+            "reservation term must start before it ends"
+        );
+        assert!(
+            config.slot_seconds > 0,
+            "reservation slot length must be positive"
+        );
+        assert!(
+            config.slot_capacity >= 0,
+            "reservation slot capacity must not be negative"
+        );
+        config
+    }
+
+    /// 予約区間 [start_at, end_at) を検証する
+    pub fn check(&self, start_at: i64, end_at: i64) -> Result<(), Error> {
+        for t in [start_at, end_at] {
+            if DateTime::from_timestamp(t, 0).is_none() {
+                return Err(Error::BadRequest(format!("invalid timestamp: {t}").into()));
+            }
+        }
+        if start_at >= end_at {
+            return Err(Error::BadRequest("start_at must be before end_at".into()));
+        }
+        if start_at >= self.term_end_at || end_at <= self.term_start_at {
+            return Err(Error::BadRequest(
+                format!(
+                    "bad reservation time range: reservations must be within {} ~ {}",
+                    self.term_start_at, self.term_end_at
+                )
+                .into(),
+            ));
+        }
+        if (start_at - self.term_start_at) % self.slot_seconds != 0
+            || (end_at - self.term_start_at) % self.slot_seconds != 0
+        {
+            return Err(Error::BadRequest(
+                format!(
+                    "start_at and end_at must be aligned to {}-second slots from {}",
+                    self.slot_seconds, self.term_start_at
+                )
+                .into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// 予約枠が期間内にあり、term_start_at から slot_seconds ごとに区切られているか。
+    /// 長さの違う予約枠があると、check を通った区間に含まれる予約枠が 1 つもなく、
+    /// 残数を減らさずに予約できてしまう
+    fn check_slots(&self, slots: &BTreeMap<i64, Slot>) -> Result<(), String> {
+        for slot in slots.values() {
+            let aligned = slot.start_at >= self.term_start_at
+                && slot.start_at < self.term_end_at
+                && (slot.start_at - self.term_start_at) % self.slot_seconds == 0;
+            if !aligned || slot.end_at != (slot.start_at + self.slot_seconds).min(self.term_end_at)
+            {
+                return Err(format!(
+                    "reservation slot {} ~ {} is not a {}-second slot of the term {} ~ {}",
+                    slot.start_at,
+                    slot.end_at,
+                    self.slot_seconds,
+                    self.term_start_at,
+                    self.term_end_at
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct Slot {
+    pub start_at: i64,
+    pub end_at: i64,
+    /// 残りの予約可能数
+    pub remaining: i64,
+}
+
+/// (start_at, end_at, 残数の増減)。[start_at, end_at) に含まれる全ての予約枠に適用する
+pub type SlotChange = (i64, i64, i64);
+
+/// 残数が足りない予約枠があった
+#[derive(Debug)]
+pub struct Unavailable;
+
+#[derive(Default)]
+struct State {
+    /// reload のたびに増やす。読み直す前に取った [`Hold`] は取り消しても台帳を変更しない
+    generation: u64,
+    /// start_at to slot
+    slots: BTreeMap<i64, Slot>,
+}
+
+#[derive(Clone)]
+pub struct SlotLedger {
+    config: Config,
+    state: Arc<Mutex<State>>,
+}
+
+fn apply(slots: &mut BTreeMap<i64, Slot>, (start_at, end_at, delta): SlotChange) {
+    if start_at >= end_at {
+        return;
+    }
+    for (_, slot) in slots.range_mut(start_at..end_at) {
+        if slot.end_at <= end_at {
+            slot.remaining += delta;
+        }
+    }
+}
+
+impl SlotLedger {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            state: Arc::default(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// reservation_slots テーブルから読み直す。テーブルが空なら設定から予約枠を作る。
+    /// 既存の予約枠には設定の定員を適用する。設定の期間と長さで区切られていなければエラー
+    pub async fn reload(&self, conn: &mut MySqlConnection) -> Result<(), Error> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM reservation_slots")
+            .fetch_one(&mut *conn)
+            .await?;
+        if count == 0 {
+            self.create_slots(conn).await?;
+        }
+        // 定員を変えても予約済みの数 (capacity - slot) は変えない
+        sqlx::query(
+            "UPDATE reservation_slots SET slot = slot + (? - capacity), capacity = ? WHERE capacity <> ?",
+        )
+        .bind(self.config.slot_capacity)
+        .bind(self.config.slot_capacity)
+        .bind(self.config.slot_capacity)
+        .execute(&mut *conn)
+        .await?;
+
+        let slots: Vec<(i64, i64, i64)> =
+            sqlx::query_as("SELECT start_at, end_at, slot FROM reservation_slots")
+                .fetch_all(&mut *conn)
+                .await?;
+        let slots = slots
+            .into_iter()
+            .map(|(start_at, end_at, remaining)| {
+                (
+                    start_at,
+                    Slot {
+                        start_at,
+                        end_at,
+                        remaining,
+                    },
+                )
+            })
+            .collect();
+        self.config
+            .check_slots(&slots)
+            .map_err(Error::InternalServerError)?;
+        self.replace(slots);
+        Ok(())
+    }
+
+    /// 台帳を置き換え、それまでに取った [`Hold`] を無効にする
+    fn replace(&self, slots: BTreeMap<i64, Slot>) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        state.slots = slots;
+    }
+
+    async fn create_slots(&self, conn: &mut MySqlConnection) -> sqlx::Result<()> {
+        let Config {
+            term_start_at,
+            term_end_at,
+            slot_seconds,
+            slot_capacity,
+        } = self.config;
+        let starts: Vec<i64> = (term_start_at..term_end_at)
+            .step_by(slot_seconds as usize)
+            .collect();
+        let mut tx = conn.begin().await?;
+        for chunk in starts.chunks(INSERT_BATCH_SIZE) {
+            let mut query_builder = sqlx::QueryBuilder::new(
+                "INSERT INTO reservation_slots (slot, capacity, start_at, end_at) ",
+            );
+            query_builder.push_values(chunk, |mut b, &start_at| {
+                b.push_bind(slot_capacity)
+                    .push_bind(slot_capacity)
+                    .push_bind(start_at)
+                    .push_bind((start_at + slot_seconds).min(term_end_at));
+            });
+            query_builder.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await
+    }
+
+    /// [from, to) に含まれる予約枠を start_at の昇順に返す
+    pub fn range(&self, from: i64, to: i64) -> Vec<Slot> {
+        if from >= to {
+            return Vec::new();
+        }
+        self.state
+            .lock()
+            .unwrap()
+            .slots
+            .range(from..to)
+            .map(|(_, slot)| *slot)
+            .filter(|slot| slot.end_at <= to)
+            .collect()
+    }
+
+    /// 全ての変更をまとめて適用する。残数が負になる予約枠があれば何も変更せずにエラーを返す。
+    ///
+    /// 返した [`Hold`] は MySQL への書き込みをコミットした後に [`Hold::keep`] すること。
+    /// keep せずに drop された場合は変更を取り消す
+    pub fn hold(&self, changes: &[SlotChange]) -> Result<Hold, Unavailable> {
+        let mut state = self.state.lock().unwrap();
+        let slots = &mut state.slots;
+        for &change in changes {
+            apply(slots, change);
+        }
+        let overbooked = changes.iter().any(|&(start_at, end_at, _)| {
+            start_at < end_at
+                && slots
+                    .range(start_at..end_at)
+                    .any(|(_, slot)| slot.end_at <= end_at && slot.remaining < 0)
+        });
+        if overbooked {
+            for &(start_at, end_at, delta) in changes.iter().rev() {
+                apply(slots, (start_at, end_at, -delta));
+            }
+            return Err(Unavailable);
+        }
+        Ok(Hold {
+            ledger: self.clone(),
+            generation: state.generation,
+            changes: changes.to_vec(),
+            kept: false,
+        })
+    }
+}
+
+/// 台帳に仮に適用した変更
+#[must_use]
+pub struct Hold {
+    ledger: SlotLedger,
+    generation: u64,
+    changes: Vec<SlotChange>,
+    kept: bool,
+}
+
+impl Hold {
+    pub fn keep(mut self) {
+        self.kept = true;
+    }
+}
+
+impl Drop for Hold {
+    fn drop(&mut self) {
+        if self.kept {
+            return;
+        }
+        let mut state = self.ledger.state.lock().unwrap();
+        if state.generation != self.generation {
+            return;
+        }
+        for &(start_at, end_at, delta) in self.changes.iter().rev() {
+            apply(&mut state.slots, (start_at, end_at, -delta));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 60 * 60;
+
+    /// 0 時から 1 時間ごとに hours 個、残数 capacity の予約枠
+    fn slots(hours: i64, capacity: i64) -> BTreeMap<i64, Slot> {
+        (0..hours)
+            .map(|i| {
+                let start_at = i * HOUR;
+                (
+                    start_at,
+                    Slot {
+                        start_at,
+                        end_at: start_at + HOUR,
+                        remaining: capacity,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn ledger(hours: i64, capacity: i64) -> SlotLedger {
+        let ledger = SlotLedger::new(Config {
+            term_start_at: 0,
+            term_end_at: hours * HOUR,
+            slot_seconds: HOUR,
+            slot_capacity: capacity,
+        });
+        ledger.replace(slots(hours, capacity));
+        ledger
+    }
+
+    fn remaining(ledger: &SlotLedger) -> Vec<i64> {
+        ledger
+            .range(0, i64::MAX)
+            .iter()
+            .map(|slot| slot.remaining)
+            .collect()
+    }
+
+    #[test]
+    fn concurrent_holds_never_exceed_capacity() {
+        let ledger = ledger(4, 5);
+        let accepted = std::sync::atomic::AtomicUsize::new(0);
+        std::thread::scope(|s| {
+            for _ in 0..64 {
+                s.spawn(|| {
+                    if let Ok(hold) = ledger.hold(&[(HOUR, 3 * HOUR, -1)]) {
+                        hold.keep();
+                        accepted.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        assert_eq!(accepted.into_inner(), 5);
+        assert_eq!(remaining(&ledger), [5, 0, 0, 5]);
+    }
+
+    #[test]
+    fn dropped_holds_are_reverted() {
+        let ledger = ledger(2, 1);
+        let hold = ledger.hold(&[(0, 2 * HOUR, -1)]).unwrap();
+        assert!(ledger.hold(&[(0, HOUR, -1)]).is_err());
+        drop(hold);
+        assert_eq!(remaining(&ledger), [1, 1]);
+    }
+
+    #[test]
+    fn reschedule_releases_then_reacquires() {
+        let ledger = ledger(3, 1);
+        ledger.hold(&[(0, 2 * HOUR, -1)]).unwrap().keep();
+
+        // 自分が確保している枠と重なる区間へ移動できる
+        ledger
+            .hold(&[(0, 2 * HOUR, 1), (HOUR, 3 * HOUR, -1)])
+            .unwrap()
+            .keep();
+        assert_eq!(remaining(&ledger), [1, 0, 0]);
+
+        // 埋まっている区間へは移動できず、元の予約はそのまま残る
+        ledger.hold(&[(0, HOUR, -1)]).unwrap().keep();
+        assert!(ledger
+            .hold(&[(HOUR, 3 * HOUR, 1), (0, 2 * HOUR, -1)])
+            .is_err());
+        assert_eq!(remaining(&ledger), [0, 0, 0]);
+
+        // コミットしなかった日時変更は、返した枠も含めて元に戻る
+        let hold = ledger
+            .hold(&[(HOUR, 3 * HOUR, 1), (2 * HOUR, 3 * HOUR, -1)])
+            .unwrap();
+        assert_eq!(remaining(&ledger), [0, 1, 0]);
+        drop(hold);
+        assert_eq!(remaining(&ledger), [0, 0, 0]);
+    }
+
+    #[test]
+    fn holds_taken_before_reload_are_not_reverted() {
+        let ledger = ledger(2, 2);
+        let hold = ledger.hold(&[(0, 2 * HOUR, -1)]).unwrap();
+        ledger.replace(slots(2, 5));
+        drop(hold);
+        assert_eq!(remaining(&ledger), [5, 5]);
+
+        // 読み直した後に取ったものは通常どおり取り消す
+        let hold = ledger.hold(&[(0, HOUR, -2)]).unwrap();
+        assert_eq!(remaining(&ledger), [3, 5]);
+        drop(hold);
+        assert_eq!(remaining(&ledger), [5, 5]);
+    }
+
+    fn check_err(config: &Config, start_at: i64, end_at: i64) -> String {
+        match config.check(start_at, end_at) {
+            Err(Error::BadRequest(message)) => message.into_owned(),
+            result => panic!("{start_at} ~ {end_at} should be rejected: {result:?}"),
+        }
+    }
+
+    fn at(year: i32, month: u32, day: u32, hour: u32) -> i64 {
+        Utc.from_utc_datetime(
+            &NaiveDate::from_ymd_opt(year, month, day)
+                .unwrap()
+                .and_hms_opt(hour, 0, 0)
+                .unwrap(),
+        )
+        .timestamp()
+    }
+
+    #[test]
+    fn check_accepts_aligned_ranges_within_term() {
+        let config = Config::default();
+        config
+            .check(config.term_start_at, config.term_start_at + HOUR)
+            .unwrap();
+        config.check(at(2024, 4, 1, 1), at(2024, 4, 1, 5)).unwrap();
+        config
+            .check(config.term_end_at - HOUR, config.term_end_at)
+            .unwrap();
+    }
+
+    #[test]
+    fn check_rejects_misaligned_times() {
+        let config = Config::default();
+        let start_at = at(2024, 4, 1, 1);
+        assert!(check_err(&config, start_at + 30 * 60, start_at + HOUR).contains("aligned"));
+        assert!(check_err(&config, start_at, start_at + 30 * 60).contains("aligned"));
+        assert!(check_err(&config, start_at + 1, start_at + HOUR + 1).contains("aligned"));
+    }
+
+    #[test]
+    fn check_rejects_empty_and_reversed_ranges() {
+        let config = Config::default();
+        let start_at = at(2024, 4, 1, 1);
+        assert!(check_err(&config, start_at, start_at).contains("before"));
+        assert!(check_err(&config, start_at + HOUR, start_at).contains("before"));
+    }
+
+    #[test]
+    fn check_rejects_out_of_range_timestamps() {
+        let config = Config::default();
+        assert!(check_err(&config, i64::MIN, config.term_start_at).contains("invalid timestamp"));
+        assert!(check_err(&config, config.term_start_at, i64::MAX).contains("invalid timestamp"));
+    }
+
+    #[test]
+    fn check_rejects_ranges_outside_term() {
+        let config = Config::default();
+        assert!(check_err(&config, at(2022, 11, 25, 1), at(2022, 11, 25, 2)).contains("within"));
+        assert!(check_err(&config, at(2026, 11, 25, 1), at(2026, 11, 25, 2)).contains("within"));
+        // 期間の境界にちょうど接するだけの区間も期間外
+        assert!(
+            check_err(&config, config.term_start_at - HOUR, config.term_start_at)
+                .contains("within")
+        );
+        assert!(
+            check_err(&config, config.term_end_at, config.term_end_at + HOUR).contains("within")
+        );
+    }
+
+    #[test]
+    fn check_slots_rejects_slots_of_another_length() {
+        let config = Config {
+            term_start_at: 0,
+            term_end_at: 3 * HOUR,
+            slot_seconds: 30 * 60,
+            slot_capacity: 5,
+        };
+        assert!(config.check_slots(&slots(2, 5)).is_err());
+
+        let config = Config {
+            slot_seconds: HOUR,
+            ..config
+        };
+        config.check_slots(&slots(2, 5)).unwrap();
+        config.check_slots(&slots(3, 5)).unwrap();
+        assert!(config.check_slots(&slots(4, 5)).is_err());
+
+        let config = Config {
+            term_start_at: 30 * 60,
+            ..config
+        };
+        assert!(config.check_slots(&slots(2, 5)).is_err());
+    }
+}
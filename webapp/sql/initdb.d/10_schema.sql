@@ -15,7 +15,9 @@ CREATE TABLE `users` (
 CREATE TABLE `icons` (
   `id` BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
   `user_id` BIGINT NOT NULL,
-  `icon_hash` VARCHAR(255) NOT NULL
+  `icon_hash` VARCHAR(255) NOT NULL,
+  -- 画像の形式 (jpeg, png, webp)
+  `format` VARCHAR(255) NOT NULL DEFAULT 'jpeg'
 ) ENGINE=InnoDB CHARACTER SET utf8mb4 COLLATE utf8mb4_bin;
 CREATE INDEX icons_user_id ON icons(user_id);
 
@@ -35,9 +37,12 @@ CREATE TABLE `livestreams` (
 CREATE TABLE `reservation_slots` (
   `id` BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
   `slot` BIGINT NOT NULL,
+  -- slot (残数) を数えたときの定員。アプリの設定と違えば予約枠を読み直すときに合わせる
+  `capacity` BIGINT NOT NULL DEFAULT 5,
   `start_at` BIGINT NOT NULL,
   `end_at` BIGINT NOT NULL
 ) ENGINE=InnoDB CHARACTER SET utf8mb4 COLLATE utf8mb4_bin;
+CREATE INDEX reservation_slots_start_at_end_at ON reservation_slots(`start_at`, `end_at`);
 
 -- ライブストリームに付与される、サービスで定義されたタグ
 CREATE TABLE `tags` (
@@ -54,6 +59,26 @@ CREATE TABLE `livestream_tags` (
 ) ENGINE=InnoDB CHARACTER SET utf8mb4 COLLATE utf8mb4_bin;
 CREATE INDEX livestream_tags_livestream_id ON livestream_tags(`livestream_id`);
 
+-- 配信予約時に指定されたコラボレーター
+CREATE TABLE `livestream_collaborators` (
+  `id` BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+  `livestream_id` BIGINT NOT NULL,
+  `user_id` BIGINT NOT NULL,
+  UNIQUE `uniq_livestream_collaborators_livestream_id_user_id` (`livestream_id`, `user_id`)
+) ENGINE=InnoDB CHARACTER SET utf8mb4 COLLATE utf8mb4_bin;
+
+-- ユーザへの通知
+CREATE TABLE `notifications` (
+  `id` BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+  `user_id` BIGINT NOT NULL,
+  -- 通知の種類 (collaborator_added, livestream_canceled, livestream_rescheduled)
+  `kind` VARCHAR(255) NOT NULL,
+  `livestream_id` BIGINT NOT NULL,
+  `created_at` BIGINT NOT NULL
+) ENGINE=InnoDB CHARACTER SET utf8mb4 COLLATE utf8mb4_bin;
+CREATE INDEX notifications_user_id_created_at ON notifications(`user_id`, `created_at`);
+CREATE INDEX notifications_livestream_id ON notifications(`livestream_id`);
+
 -- ライブ配信視聴履歴
 CREATE TABLE `livestream_viewers_history` (
   `id` BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
@@ -69,7 +94,10 @@ CREATE TABLE `livecomments` (
   `livestream_id` BIGINT NOT NULL,
   `comment` VARCHAR(255) NOT NULL,
   `tip` BIGINT NOT NULL DEFAULT 0,
-  `created_at` BIGINT NOT NULL
+  `created_at` BIGINT NOT NULL,
+  -- 非表示にされた日時と、NGワードによる場合はその NG ワード (ng_words.id)
+  `deleted_at` BIGINT DEFAULT NULL,
+  `deleted_by_ng_word_id` BIGINT DEFAULT NULL
 ) ENGINE=InnoDB CHARACTER SET utf8mb4 COLLATE utf8mb4_bin;
 CREATE INDEX livecomments_livesream_id ON livecomments(livestream_id);
 
@@ -79,7 +107,10 @@ CREATE TABLE `livecomment_reports` (
   `user_id` BIGINT NOT NULL,
   `livestream_id` BIGINT NOT NULL,
   `livecomment_id` BIGINT NOT NULL,
-  `created_at` BIGINT NOT NULL
+  -- 配信者による対応状況 (open, dismissed, actioned)
+  `status` VARCHAR(255) NOT NULL DEFAULT 'open',
+  `created_at` BIGINT NOT NULL,
+  UNIQUE `uniq_livecomment_reports_user_id_livecomment_id` (`user_id`, `livecomment_id`)
 ) ENGINE=InnoDB CHARACTER SET utf8mb4 COLLATE utf8mb4_bin;
 
 -- 配信者からのNGワード登録
@@ -88,6 +119,8 @@ CREATE TABLE `ng_words` (
   `user_id` BIGINT NOT NULL,
   `livestream_id` BIGINT NOT NULL,
   `word` VARCHAR(255) NOT NULL,
+  -- 照合方法 (substring, word, regex, normalized)
+  `mode` VARCHAR(255) NOT NULL DEFAULT 'substring',
   `created_at` BIGINT NOT NULL
 ) ENGINE=InnoDB CHARACTER SET utf8mb4 COLLATE utf8mb4_bin;
 CREATE INDEX ng_words_word ON ng_words(`word`);
@@ -103,6 +136,14 @@ CREATE TABLE `reactions` (
   `created_at` BIGINT NOT NULL
 ) ENGINE=InnoDB CHARACTER SET utf8mb4 COLLATE utf8mb4_bin;
 
+-- ライブ配信ごとの人気度 (リアクション数 + 表示中のライブコメントのチップ合計)。検索の popular 順に使う
+CREATE TABLE `livestream_score` (
+  `livestream_id` BIGINT NOT NULL,
+  `score` BIGINT NOT NULL DEFAULT 0,
+  UNIQUE `uniq_livestream_id` (`livestream_id`)
+) ENGINE=InnoDB CHARACTER SET utf8mb4 COLLATE utf8mb4_bin;
+CREATE INDEX livestream_score_score_livestream_id ON livestream_score(`score`, `livestream_id`);
+
 -- ユーザごとに、紐づく配信について、累計リアクション数、累計ライブコメント数、累計売上金額を算出
 CREATE TABLE `user_score` (
   `user_id` BIGINT NOT NULL,
@@ -159,4 +200,31 @@ BEGIN
     total_livecomments=current_comments+1
   WHERE user_id=uid;
 END //
+
+DROP TRIGGER IF EXISTS livestream_score_livestream_trigger //
+CREATE TRIGGER livestream_score_livestream_trigger
+AFTER INSERT ON livestreams FOR EACH ROW
+  INSERT INTO livestream_score (livestream_id) VALUES (NEW.id) //
+
+DROP TRIGGER IF EXISTS livestream_score_reaction_trigger //
+CREATE TRIGGER livestream_score_reaction_trigger
+AFTER INSERT ON reactions FOR EACH ROW
+  UPDATE livestream_score SET score = score + 1 WHERE livestream_id = NEW.livestream_id //
+
+DROP TRIGGER IF EXISTS livestream_score_livecomment_trigger //
+CREATE TRIGGER livestream_score_livecomment_trigger
+AFTER INSERT ON livecomments FOR EACH ROW
+  UPDATE livestream_score SET score = score + NEW.tip WHERE livestream_id = NEW.livestream_id //
+
+-- 非表示にしたライブコメントのチップは数えない
+DROP TRIGGER IF EXISTS livestream_score_livecomment_update_trigger //
+CREATE TRIGGER livestream_score_livecomment_update_trigger
+AFTER UPDATE ON livecomments FOR EACH ROW
+BEGIN
+  IF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+    UPDATE livestream_score SET score = score - OLD.tip WHERE livestream_id = OLD.livestream_id;
+  ELSEIF OLD.deleted_at IS NOT NULL AND NEW.deleted_at IS NULL THEN
+    UPDATE livestream_score SET score = score + NEW.tip WHERE livestream_id = NEW.livestream_id;
+  END IF;
+END //
 DELIMITER ;